@@ -307,7 +307,7 @@ impl ApplicationHandler for State<'_> {
                 } else {
                     size *= 4.0 / 5.0
                 };
-                self.font_size = (size.max(3.0).min(25000.0) * 2.0).round() / 2.0;
+                self.font_size = (size.clamp(3.0, 25000.0) * 2.0).round() / 2.0;
             }
             WindowEvent::RedrawRequested => {
                 let brush = self.brush.as_mut().unwrap();
@@ -338,14 +338,12 @@ impl ApplicationHandler for State<'_> {
                     wgpu::CurrentSurfaceTexture::Occluded => return,
                     _ => {
                         surface.configure(device, config);
-                        let frame = match surface.get_current_texture() {
+                        match surface.get_current_texture() {
                             wgpu::CurrentSurfaceTexture::Success(s) => s,
                             e => {
                                 panic!("Failed to acquire next surface texture: {:?}", e)
                             }
-                        };
-
-                        frame
+                        }
                     }
                 };
                 let view = frame
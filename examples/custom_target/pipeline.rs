@@ -42,7 +42,7 @@ pub fn create_pipeline(
         immediate_size: 0,
     });
 
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Custom Surface Render Pipeline"),
         layout: Some(&layout),
         vertex: wgpu::VertexState {
@@ -76,6 +76,5 @@ pub fn create_pipeline(
         }),
         multiview_mask: None,
         cache: None,
-    });
-    pipeline
+    })
 }
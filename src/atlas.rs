@@ -0,0 +1,1638 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::BuildHasher,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use glyph_brush::{
+    BrushAction, DefaultSectionHasher, FontId, GlyphBrushBuilder, GlyphCruncher,
+    OwnedSection, Rectangle, Section, SectionGlyph,
+    ab_glyph::{
+        CodepointIdIter, Font, FontArc, Glyph, GlyphId, GlyphSvg, Outline, OutlinedGlyph,
+        PxScale, Rect, ScaleFont, point, v2,
+    },
+};
+
+use crate::{
+    error::BrushError,
+    grid::{GlyphPositioning, PixelGrid},
+    persist::{self, StoredGlyph},
+    pipeline::GlyphQuad,
+    style::SectionExtra,
+    upload::Uploader,
+};
+
+/// Glyph cache texture together with the glyph layout cache, shareable between
+/// multiple [`TextBrush`](crate::TextBrush)es.
+///
+/// Every brush owns an atlas, accessible with
+/// [`TextBrush::atlas()`](crate::TextBrush::atlas). Brushes built with
+/// [`BrushBuilder::using_atlas()`](crate::BrushBuilder::using_atlas) share it, so
+/// glyphs used by several brushes are rasterized, uploaded and stored only once,
+/// regardless of the render formats, depth states or matrices of the brushes.
+/// Cloning the atlas only clones the handle.
+///
+/// # Resizing
+///
+/// Glyphs used by the last processed frame of every brush sharing the atlas stay
+/// cached. When new glyphs don't fit, the atlas is repacked or grown, moving the
+/// already cached glyphs. Brushes processed earlier in the same frame would then
+/// draw outdated glyphs, which is reported by
+/// [`TextBrush::is_outdated()`](crate::TextBrush::is_outdated). Such brushes should
+/// be queued and processed again before drawing. Since all of their glyphs are already
+/// cached at that point, doing so doesn't rebuild the atlas again.
+///
+/// # Redrawing
+///
+/// The brushes sharing an atlas process its layout cache in turns, so it never
+/// matches the previous frame of a single brush. Their processings are therefore
+/// never reported as redraws ([`BrushStats::redraw`](crate::BrushStats::redraw)),
+/// although the layouts of unchanged sections stay cached and the vertex buffers
+/// only rewrite the sections which changed.
+///
+/// # Pages
+///
+/// The cache texture grows up to [`wgpu::Limits::max_texture_dimension_2d`]. Once
+/// the glyphs of a frame don't fit a texture of that size, the atlas spills them
+/// into additional textures (pages) of the maximum size, each caching the glyphs of
+/// a consecutive range of the queued sections. Glyphs are drawn from the page of
+/// their section, so only a single section with more glyphs than fit one page
+/// fails with [`BrushError::TooBigCacheTexture`]. See [`GlyphAtlas::page_count()`].
+///
+/// A brush using the atlas alone queues its sections into the glyph cache right
+/// away until the texture reaches a quarter of the maximum size, instead of keeping
+/// a copy for spilling them. A frame growing the texture beyond the maximum from
+/// below that size fails with [`BrushError::TooBigCacheTexture`] as well, and its
+/// glyphs are spilled into pages starting with the next frame.
+///
+/// Once the glyphs of the last processed frames fit a third of a texture of the
+/// maximum size again, the pages are merged back into a single texture.
+///
+/// # Eviction
+///
+/// The cache textures don't shrink on their own. After the amount of cached text
+/// drops, [`TextBrush::trim_cache()`](crate::TextBrush::trim_cache) repacks the
+/// glyphs into a smaller texture, which also happens automatically once the
+/// textures exceed the budget set with
+/// [`BrushBuilder::with_cache_budget()`](crate::BrushBuilder::with_cache_budget).
+/// Which glyphs stay cached besides the ones of the last processed frame is
+/// decided by the [`EvictionPolicy`].
+pub struct GlyphAtlas<F = FontArc, H = DefaultSectionHasher> {
+    shared: Arc<Shared<F, H>>,
+}
+
+struct Shared<F, H> {
+    fonts: Arc<[F]>,
+    /// Padding of the glyph outlines, see [`CacheSettings::glyph_padding()`].
+    padding: u32,
+    /// Shared with the fonts of the glyph caches, see [`Placeholders`].
+    placeholders: Arc<Mutex<Placeholders>>,
+    /// Incremented whenever the cached glyphs move or the texture is recreated.
+    generation: AtomicU64,
+    state: Mutex<AtlasState<F, H>>,
+}
+
+/// Policy for evicting cached glyphs which aren't used by the last processed frame,
+/// see [`BrushBuilder::with_eviction_policy()`](crate::BrushBuilder::with_eviction_policy).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Keeps only the glyphs of the last processed frame. Other glyphs are evicted
+    /// as soon as new glyphs don't fit the free space of the cache texture.
+    #[default]
+    Frame,
+    /// Also keeps the glyphs used within the last `max_age` frames cached while they
+    /// fit the free space of the cache texture and the
+    /// [budget](crate::BrushBuilder::with_cache_budget), evicting the least recently
+    /// used glyphs first. Text shown again shortly after being hidden then doesn't have to
+    /// be rasterized and uploaded again.
+    ///
+    /// A frame ends once a brush sharing the atlas is processed again, so processing
+    /// several brushes once each counts as a single frame.
+    ///
+    /// Glyphs are tracked while being queued, which adds some work to every queued
+    /// section. Only applies while the atlas has a single page.
+    Lru {
+        /// Number of frames a glyph stays cached after it was last used.
+        max_age: u32,
+    },
+}
+
+/// Atlas settings of the [`BrushBuilder`](crate::BrushBuilder) besides the ones
+/// of the `glyph_brush` cache.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct CacheSettings {
+    /// Size of the cache textures in bytes above which they are trimmed.
+    pub budget: Option<u64>,
+    pub eviction: EvictionPolicy,
+    /// Whether the cache textures have mipmaps.
+    pub mipmaps: bool,
+}
+
+impl CacheSettings {
+    /// Returns the number of mip levels of the cache textures.
+    pub fn mip_levels(&self) -> u32 {
+        if self.mipmaps { MIP_LEVELS } else { 1 }
+    }
+
+    /// Returns the empty texels added around the glyph outlines, on top of the
+    /// single texel of padding the draw cache adds.
+    fn glyph_padding(&self) -> u32 {
+        if self.mipmaps { MIP_PADDING - 1 } else { 0 }
+    }
+}
+
+/// Mip levels of mipmapped cache textures. A texel of the last level covers
+/// `2^(MIP_LEVELS - 1)` texels of the first one, so bilinear samples of every level
+/// stay inside the glyph as long as the glyphs are aligned to and padded by as many
+/// texels.
+const MIP_LEVELS: u32 = 3;
+/// Empty texels around mipmapped glyphs, see [`MIP_LEVELS`].
+const MIP_PADDING: u32 = 1 << (MIP_LEVELS - 1);
+// Mipmapped glyphs are aligned by the draw cache with `align_4x4`.
+const _: () = assert!(MIP_PADDING <= 4);
+
+/// Format of the cache textures.
+const CACHE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Brush identifier of [`GlyphAtlas::cache_pinned()`], never assigned to a brush.
+const PINNING: u32 = u32::MAX;
+
+/// Glyph identity in the draw cache: font, glyph, scale and subpixel position.
+type GlyphKey = (usize, u16, u32, u32, u16, u16);
+
+/// Restored glyphs which are outlined without curves, so the draw cache allocates
+/// their space without rasterizing them. Their stored bitmaps are uploaded instead.
+#[derive(Debug, Default)]
+struct Placeholders {
+    /// Keys of the restored glyphs, empty unless restoring.
+    keys: HashSet<GlyphKey>,
+    /// Keys of the restored glyphs outlined by the draw cache.
+    outlined: HashSet<GlyphKey>,
+    position_tolerance: f32,
+}
+
+/// The queued glyphs don't fit a single cache texture of the maximum size.
+struct Spill;
+
+/// Glyph tracked by the [`EvictionPolicy::Lru`].
+struct RecentGlyph {
+    /// The glyph, positioned at its subpixel offset.
+    glyph: SectionGlyph,
+    /// Estimated size in the cache texture, in texels.
+    area: u64,
+    last_used: u64,
+}
+
+/// Mutable atlas data, accessed by the brushes while holding the lock.
+pub(crate) struct AtlasState<F, H> {
+    /// Layout cache, and the glyph cache while the atlas has a single page.
+    pub inner: glyph_brush::GlyphBrush<GlyphQuad, SectionExtra, AtlasFont<F>, H>,
+    pub texture: wgpu::Texture,
+    /// Whether the glyph cache of `inner` is empty since its texture was created.
+    empty: bool,
+    /// Whether sections were queued into `inner` directly since the last
+    /// processing, see [`AtlasState::buffers_sections()`].
+    queued: bool,
+    /// Glyph caches used instead of `inner` once the glyphs don't fit a single
+    /// texture of the maximum size. Empty until then.
+    pages: Vec<Page<F>>,
+    /// Index of the first section cached by each page after the first one, for
+    /// each brush.
+    splits: HashMap<u32, Vec<usize>>,
+    /// Glyph cache settings (without fonts) for building the caches of new pages.
+    page_builder: GlyphBrushBuilder<()>,
+    max_dimension: u32,
+
+    brushes: u32,
+    next_brush: u32,
+    /// Sections of the last processed frame of each brush, kept cached while
+    /// the other brushes are processed. Only used while the atlas is shared.
+    retained: HashMap<u32, Vec<OwnedSection<SectionExtra>>>,
+
+    settings: CacheSettings,
+    /// Smallest dimensions the texture is trimmed to.
+    initial_dimensions: (u32, u32),
+    /// Set by `TextBrush::trim_cache()`, trims before the next processing.
+    trim: bool,
+    /// Working set when the textures were last trimmed for exceeding the budget.
+    /// They are only trimmed for the budget again once the working set shrinks.
+    trim_floor: Option<u64>,
+    /// Estimated texels used by the last processed frame of each brush.
+    working_set: HashMap<u32, u64>,
+    /// Number of frames, the clock of the recently used glyphs.
+    frame: u64,
+    /// Brushes processed during the current frame.
+    processed: HashSet<u32>,
+    recent: HashMap<GlyphKey, RecentGlyph>,
+    /// Glyphs cached ahead of time, kept cached by every processing.
+    pinned: BTreeMap<GlyphKey, SectionGlyph>,
+    /// Estimated texels used by the pinned glyphs.
+    pinned_area: u64,
+    /// Number of times the cache textures were resized, trimmed or a page was added.
+    pub resizes: u64,
+    /// Subpixel position tolerance of the glyph caches.
+    position_tolerance: f32,
+}
+
+/// Glyph cache and texture of an additional page.
+pub(crate) struct Page<F> {
+    inner: glyph_brush::GlyphBrush<GlyphQuad, SectionExtra, AtlasFont<F>>,
+    texture: wgpu::Texture,
+    /// Whether the glyph cache is empty since the texture was created.
+    empty: bool,
+    /// Vertices of the last frame processed with this page.
+    vertices: Vec<GlyphQuad>,
+}
+
+impl<F, H> AtlasState<F, H> {
+    /// Returns `true` if more than one brush uses the atlas.
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        self.brushes > 1
+    }
+
+    /// Returns `true` if the glyphs are cached in multiple pages.
+    #[inline]
+    pub fn is_paged(&self) -> bool {
+        !self.pages.is_empty()
+    }
+
+    /// Returns the smallest dimensions, starting from the initial ones, fitting the
+    /// working set of all brushes with some slack for packing.
+    fn trimmed_dimensions(&self) -> (u32, u32) {
+        let needed = (self.working_set.values().sum::<u64>() + self.pinned_area) * 3 / 2;
+        let max = self.max_dimension;
+        let (mut width, mut height) = self.initial_dimensions;
+        while (width as u64 * height as u64) < needed && (width < max || height < max) {
+            if width <= height && width < max {
+                width = (width * 2).min(max);
+            } else {
+                height = (height * 2).min(max);
+            }
+        }
+        (width, height)
+    }
+
+    /// Returns the number of pages.
+    pub fn page_count(&self) -> usize {
+        self.pages.len().max(1)
+    }
+
+    /// Returns the cache textures of all pages.
+    pub fn textures(&self) -> Vec<wgpu::Texture> {
+        if self.pages.is_empty() {
+            vec![self.texture.clone()]
+        } else {
+            self.pages.iter().map(|page| page.texture.clone()).collect()
+        }
+    }
+}
+
+impl<F: Font, H: BuildHasher> GlyphAtlas<F, H> {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        mut builder: GlyphBrushBuilder<F, H>,
+        settings: CacheSettings,
+    ) -> Self {
+        let fonts: Arc<[F]> = std::mem::take(&mut builder.font_data).into();
+        let placeholders = Arc::new(Mutex::new(Placeholders::default()));
+        if settings.mipmaps {
+            builder.draw_cache_builder = builder.draw_cache_builder.align_4x4(true);
+        }
+        let mut page_builder = GlyphBrushBuilder::without_fonts();
+        page_builder.cache_glyph_positioning = builder.cache_glyph_positioning;
+        page_builder.cache_redraws = builder.cache_redraws;
+        page_builder.draw_cache_builder = builder.draw_cache_builder.clone();
+        let inner = builder
+            .replace_fonts(|_| {
+                (0..fonts.len())
+                    .map(|id| AtlasFont {
+                        fonts: fonts.clone(),
+                        id,
+                        padding: settings.glyph_padding(),
+                        placeholders: placeholders.clone(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .build();
+        let initial_dimensions = inner.texture_dimensions();
+        let position_tolerance = page_builder
+            .draw_cache_builder
+            .clone()
+            .build()
+            .position_tolerance();
+        let texture = create_texture(device, initial_dimensions, settings.mip_levels());
+
+        Self {
+            shared: Arc::new(Shared {
+                fonts,
+                padding: settings.glyph_padding(),
+                placeholders,
+                generation: AtomicU64::new(0),
+                state: Mutex::new(AtlasState {
+                    inner,
+                    texture,
+                    empty: true,
+                    queued: false,
+                    pages: Vec::new(),
+                    splits: HashMap::new(),
+                    page_builder,
+                    max_dimension: device.limits().max_texture_dimension_2d,
+                    brushes: 0,
+                    next_brush: 0,
+                    retained: HashMap::new(),
+                    settings,
+                    initial_dimensions,
+                    trim: false,
+                    trim_floor: None,
+                    working_set: HashMap::new(),
+                    frame: 0,
+                    processed: HashSet::new(),
+                    recent: HashMap::new(),
+                    pinned: BTreeMap::new(),
+                    pinned_area: 0,
+                    resizes: 0,
+                    position_tolerance,
+                }),
+            }),
+        }
+    }
+
+    /// Returns the cache texture dimensions, those of the first page if the atlas
+    /// has multiple pages.
+    pub fn texture_dimensions(&self) -> (u32, u32) {
+        self.lock().dimensions()
+    }
+
+    /// Returns the memory used by the cache textures of all pages, in bytes.
+    pub fn texture_bytes(&self) -> u64 {
+        self.lock().texture_bytes()
+    }
+
+    /// Returns the maximum difference in subpixel position for which a cached glyph
+    /// is reused, set with
+    /// [`BrushBuilder::draw_cache_position_tolerance()`](crate::BrushBuilder::draw_cache_position_tolerance).
+    pub fn position_tolerance(&self) -> f32 {
+        self.lock().position_tolerance
+    }
+
+    /// Pins the glyphs of the `chars` in every font and scale, so every processing
+    /// keeps them cached. Glyphs are pinned at every horizontal subpixel offset the
+    /// cache distinguishes (only at whole pixels if pixel aligned), on the baseline
+    /// of the first line of a section at an integer position.
+    ///
+    /// Nothing is pinned if one of the `fonts` doesn't exist.
+    pub(crate) fn pin(
+        &self,
+        fonts: &[FontId],
+        scales: &[PxScale],
+        chars: &[char],
+        grid: PixelGrid,
+    ) -> Result<(), BrushError> {
+        if let Some(&font_id) = fonts.iter().find(|id| id.0 >= self.fonts().len()) {
+            return Err(BrushError::UnknownFont(font_id));
+        }
+        let offsets: Vec<f32> = match grid.positioning {
+            // Sections are laid out in device pixels and pixel aligned glyphs rounded
+            // to whole ones, so every scale factor, fractional ones included, leaves
+            // only the offset zero.
+            GlyphPositioning::PixelAligned => vec![0.0],
+            _ => {
+                let tolerance = self.position_tolerance();
+                (0..=(1.0 / tolerance + 0.5) as u16)
+                    // Offset inside the step, avoiding the rounding boundaries.
+                    .map(|step| (step as f32 * tolerance - 0.5).clamp(-0.49, 0.49))
+                    .collect()
+            }
+        };
+        let mut glyphs = Vec::new();
+        for &font_id in fonts {
+            let font = &self.fonts()[font_id.0];
+            for &scale in scales {
+                let ascent = font.as_scaled(scale).ascent();
+                let baseline = match grid.positioning {
+                    GlyphPositioning::Subpixel => ascent,
+                    _ => ascent.round(),
+                }
+                .rem_euclid(1.0);
+                for &c in chars {
+                    let id = font.glyph_id(c);
+                    for &x in &offsets {
+                        glyphs.push(SectionGlyph {
+                            section_index: 0,
+                            byte_index: 0,
+                            glyph: id.with_scale_and_position(scale, point(x, baseline)),
+                            font_id,
+                        });
+                    }
+                }
+            }
+        }
+        let mut state = self.lock();
+        for glyph in glyphs {
+            state.pin(glyph);
+        }
+        Ok(())
+    }
+
+    /// Returns the pinned glyphs and, with the [`EvictionPolicy::Lru`], the recently
+    /// used ones together with their bitmaps, rasterized like the draw cache does.
+    pub(crate) fn stored_glyphs(&self) -> Vec<StoredGlyph> {
+        let glyphs: Vec<SectionGlyph> = {
+            let state = self.lock();
+            let recent = state
+                .recent
+                .iter()
+                .filter(|(key, _)| !state.pinned.contains_key(key))
+                .map(|(_, recent)| recent.glyph.clone());
+            state.pinned.values().cloned().chain(recent).collect()
+        };
+        glyphs
+            .into_iter()
+            .filter_map(|glyph| {
+                let font = &self.fonts()[glyph.font_id.0];
+                let outline = font.outline(glyph.glyph.id)?;
+                let outlined =
+                    pad_outline(font, glyph.glyph.clone(), outline, self.shared.padding);
+                let (width, height) = padded_size(outlined.px_bounds());
+                let mut texels = vec![0; width as usize * height as usize];
+                outlined.draw(|x, y, coverage| {
+                    // `+ 1` skips the padding.
+                    let index = (y + 1) as usize * width as usize + x as usize + 1;
+                    texels[index] = (coverage * 255.0) as u8;
+                });
+                Some(StoredGlyph {
+                    glyph,
+                    width,
+                    height,
+                    texels,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the bitmap of the `stored` glyph has the size the draw
+    /// cache allocates for it.
+    pub(crate) fn fits_bitmap(&self, stored: &StoredGlyph) -> bool {
+        self.fonts()
+            .get(stored.glyph.font_id.0)
+            .and_then(|font| {
+                let outline = font.outline(stored.glyph.glyph.id)?;
+                Some(pad_outline(
+                    font,
+                    stored.glyph.glyph.clone(),
+                    outline,
+                    self.shared.padding,
+                ))
+            })
+            .is_some_and(|outlined| {
+                padded_size(outlined.px_bounds()) == (stored.width, stored.height)
+            })
+    }
+
+    /// Returns the hashes of the font data, identifying the fonts in atlas files.
+    pub(crate) fn font_hashes(&self) -> Vec<u64> {
+        self.fonts()
+            .iter()
+            .map(|font| persist::font_hash(font.font_data()))
+            .collect()
+    }
+
+    /// Unpins all glyphs, so they can be evicted again.
+    pub(crate) fn unpin(&self) {
+        let mut state = self.lock();
+        state.pinned.clear();
+        state.pinned_area = 0;
+    }
+}
+
+impl<F: Font, H: BuildHasher + Clone> GlyphAtlas<F, H> {
+    /// Returns a builder with the glyph cache settings of the atlas, without fonts.
+    pub(crate) fn to_builder(&self) -> GlyphBrushBuilder<F, H> {
+        self.lock().inner.to_builder().replace_fonts(|_| Vec::new())
+    }
+}
+
+impl<F, H> GlyphAtlas<F, H> {
+    /// Returns the available fonts.
+    ///
+    /// The `FontId` corresponds to the index of the font data.
+    pub fn fonts(&self) -> &[F] {
+        &self.shared.fonts
+    }
+
+    /// Returns the number of cache textures, `1` unless the glyphs didn't fit
+    /// a single texture. Read about [pages](GlyphAtlas#pages) for more info.
+    pub fn page_count(&self) -> usize {
+        self.lock().page_count()
+    }
+
+    /// Returns the number of brushes using the atlas.
+    pub fn brush_count(&self) -> usize {
+        self.lock().brushes as usize
+    }
+
+    #[inline]
+    pub(crate) fn lock(&self) -> MutexGuard<'_, AtlasState<F, H>> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.shared.generation.load(Ordering::Acquire)
+    }
+
+    fn placeholders(&self) -> MutexGuard<'_, Placeholders> {
+        self.shared
+            .placeholders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[inline]
+    fn invalidate(&self) {
+        self.shared.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Registers a new brush and returns its identifier.
+    pub(crate) fn register(&self) -> u32 {
+        let mut state = self.lock();
+        // Glyphs of the brushes which were using the atlas alone aren't retained yet.
+        if state.brushes > 0 {
+            self.invalidate();
+        }
+        state.brushes += 1;
+        state.next_brush += 1;
+        state.next_brush - 1
+    }
+
+    pub(crate) fn unregister(&self, brush: u32) {
+        let mut state = self.lock();
+        state.brushes -= 1;
+        state.retained.remove(&brush);
+        state.splits.remove(&brush);
+        state.working_set.remove(&brush);
+        state.processed.remove(&brush);
+    }
+
+    /// Trims the cache textures to the working set before the next processing.
+    pub(crate) fn request_trim(&self) {
+        self.lock().trim = true;
+    }
+}
+
+impl<F, H> GlyphAtlas<F, H>
+where
+    F: Font + Send + Sync,
+    H: BuildHasher + Clone,
+{
+    /// Caches the pinned glyphs without processing the sections of any brush,
+    /// retaining the glyphs of all brushes. A brush using the atlas alone doesn't
+    /// retain its glyphs, which may be evicted to make room, so the brushes become
+    /// outdated whenever a glyph is uploaded.
+    pub(crate) fn cache_pinned(
+        &self,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+    ) -> Result<(), BrushError> {
+        // Caching now would process the sections queued directly, so they are
+        // cached together with the pinned glyphs when processing them instead.
+        if self.lock().queued {
+            return Ok(());
+        }
+        let uploads = uploader.glyphs;
+        let result = self.process(PINNING, Vec::new(), device, uploader);
+        if uploader.glyphs > uploads {
+            self.invalidate();
+        }
+        let mut state = self.lock();
+        state.processed.remove(&PINNING);
+        state.splits.remove(&PINNING);
+        state.working_set.remove(&PINNING);
+        state.retained.remove(&PINNING);
+        result.map(|_| ())
+    }
+
+    /// Pins the restored `glyphs` and caches them with their stored bitmaps instead
+    /// of rasterizing them, retaining the glyphs of all brushes. The glyphs are
+    /// rasterized like [`GlyphAtlas::cache_pinned()`] does once the atlas has pages.
+    pub(crate) fn restore(
+        &self,
+        glyphs: &[StoredGlyph],
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+    ) -> Result<(), BrushError> {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        for stored in glyphs {
+            state.pin(stored.glyph.clone());
+        }
+        // Rasterized with the sections queued directly, see `cache_pinned()`.
+        if state.queued {
+            return Ok(());
+        }
+        if state.trim(device) {
+            self.invalidate();
+        }
+        if !state.is_paged() {
+            // Visible everywhere, tagged with the index of their stored glyph.
+            let positioned = glyphs
+                .iter()
+                .enumerate()
+                .map(|(index, stored)| SectionGlyph {
+                    section_index: index,
+                    ..stored.glyph.clone()
+                })
+                .collect();
+            let extras = (0..glyphs.len())
+                .map(|index| SectionExtra {
+                    color: [0.0; 4],
+                    z: 0.0,
+                    section: index as u64,
+                    grid: PixelGrid::default(),
+                })
+                .collect();
+            let everywhere = Rect {
+                min: point(f32::MIN, f32::MIN),
+                max: point(f32::MAX, f32::MAX),
+            };
+            state
+                .inner
+                .queue_pre_positioned(positioned, extras, everywhere);
+            for sections in state.retained.values() {
+                for section in sections {
+                    state.inner.keep_cached(section.to_borrowed());
+                }
+            }
+            state.queue_kept_glyphs(PINNING);
+
+            let position_tolerance = state.position_tolerance;
+            *self.placeholders() = Placeholders {
+                keys: glyphs
+                    .iter()
+                    .map(|stored| section_glyph_key(&stored.glyph, position_tolerance))
+                    .collect(),
+                outlined: HashSet::new(),
+                position_tolerance,
+            };
+            let uploads = uploader.glyphs;
+            let result = self.process_queue(state, device, uploader);
+            let outlined = std::mem::take(&mut *self.placeholders()).outlined;
+
+            if let Ok(vertices) = result {
+                state.upload_stored(
+                    glyphs,
+                    &vertices.unwrap_or_default(),
+                    &outlined,
+                    uploader,
+                );
+                // The glyphs of a brush using the atlas alone aren't retained, so
+                // their rows may have been reused.
+                if uploader.glyphs > uploads {
+                    self.invalidate();
+                }
+                return Ok(());
+            }
+        }
+        drop(guard);
+        self.cache_pinned(device, uploader)
+    }
+
+    /// Processes the sections queued by the `brush`, caching their glyphs while
+    /// retaining the glyphs of the other brushes.
+    ///
+    /// Returns `None` if the vertices haven't changed since the last processing.
+    pub(crate) fn process(
+        &self,
+        brush: u32,
+        pending: Vec<OwnedSection<SectionExtra>>,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+    ) -> Result<Option<Vec<GlyphQuad>>, BrushError> {
+        let mut state = self.lock();
+        let state = &mut *state;
+        let queued = std::mem::take(&mut state.queued);
+        if !state.processed.insert(brush) {
+            state.frame += 1;
+            state.processed = HashSet::from([brush]);
+        }
+        if state.trim(device) {
+            self.invalidate();
+        }
+        if state.is_paged() {
+            return self.process_pages(state, brush, pending, device, uploader);
+        }
+
+        for section in &pending {
+            state.touch(&section.to_borrowed());
+            let section = section.to_borrowed();
+            let layout = SectionExtra::layout(&section);
+            state.inner.queue_custom_layout(section, &layout);
+        }
+        for (_, sections) in state.retained.iter().filter(|(id, _)| **id != brush) {
+            for section in sections {
+                state.inner.keep_cached(section.to_borrowed());
+            }
+        }
+        state.queue_kept_glyphs(brush);
+
+        let Ok(vertices) = self.process_queue(state, device, uploader) else {
+            if queued {
+                // The sections queued directly were dropped together with the
+                // queue, so only the following frames can be spilled into pages.
+                let max = state.max_dimension;
+                state.pages.push(state.new_page(device, (max, max)));
+                state.resizes += 1;
+                return Err(BrushError::TooBigCacheTexture(max));
+            }
+            return self.process_pages(state, brush, pending, device, uploader);
+        };
+
+        if let Some(vertices) = &vertices {
+            let texels = texels(vertices, state.inner.texture_dimensions());
+            state.working_set.insert(brush, texels);
+        }
+        if state.is_shared() {
+            state.retained.insert(brush, pending);
+        }
+        Ok(vertices)
+    }
+
+    /// Caches the glyphs queued in `inner`, growing its texture until they fit.
+    ///
+    /// Fails after dropping the queue if they don't fit a texture of the maximum
+    /// size, so the glyphs have to be spilled into pages.
+    fn process_queue(
+        &self,
+        state: &mut AtlasState<F, H>,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+    ) -> Result<Option<Vec<GlyphQuad>>, Spill> {
+        loop {
+            let (mut moved, mut uploaded) = (false, false);
+            let (width, _) = state.inner.texture_dimensions();
+            let empty = state.empty;
+            // Contains BrushAction enum which marks for
+            // drawing or redrawing (using old data).
+            let brush_action = state.inner.process_queued(
+                |rect, data| {
+                    moved |= is_repack(rect, width, empty);
+                    uploaded = true;
+                    uploader.write_glyph(&state.texture, rect, data);
+                },
+                GlyphQuad::new,
+            );
+            uploader.flush_glyphs(&state.texture);
+            if moved {
+                self.invalidate();
+            }
+            state.empty &= !uploaded;
+
+            match brush_action {
+                Ok(BrushAction::Draw(vertices)) => return Ok(Some(vertices)),
+                Ok(BrushAction::ReDraw) => return Ok(None),
+
+                Err(glyph_brush::BrushError::TextureTooSmall { suggested }) => {
+                    if log::log_enabled!(log::Level::Warn) {
+                        log::warn!(
+                            "Resizing cache texture! This should be avoided \
+                            by building TextBrush with BrushBuilder::initial_cache_size() \
+                            and providing bigger cache texture dimensions."
+                        );
+                    }
+                    // Texture resizing:
+                    let max_image_dimension = device.limits().max_texture_dimension_2d;
+                    let (width, height) = if suggested.0 > max_image_dimension
+                        || suggested.1 > max_image_dimension
+                    {
+                        if state.inner.texture_dimensions().0 < max_image_dimension
+                            || state.inner.texture_dimensions().1 < max_image_dimension
+                        {
+                            (max_image_dimension, max_image_dimension)
+                        } else {
+                            log::warn!(
+                                "The glyphs don't fit a single cache texture, \
+                                spilling them into multiple pages."
+                            );
+                            // Drops the queue of `inner`, which is only used for
+                            // layouts from now on.
+                            state.reset_inner((1, 1));
+                            state.texture = create_texture(device, (1, 1), 1);
+                            self.invalidate();
+                            return Err(Spill);
+                        }
+                    } else {
+                        suggested
+                    };
+                    let mip_levels = state.settings.mip_levels();
+                    if state.settings.budget.is_some_and(|budget| {
+                        texture_size((width, height), mip_levels) > budget
+                    }) {
+                        log::warn!(
+                            "The glyphs of the frame don't fit the cache budget, \
+                            growing the cache texture to {width}x{height}."
+                        );
+                    }
+                    state.texture = create_texture(
+                        device,
+                        (width, height),
+                        state.settings.mip_levels(),
+                    );
+                    state.resizes += 1;
+                    state.inner.resize_texture(width, height);
+                    state.empty = true;
+                    self.invalidate();
+                }
+            }
+        }
+    }
+
+    /// Processes the sections of the `brush`, caching the glyphs of each
+    /// consecutive range of sections in its own page.
+    fn process_pages(
+        &self,
+        state: &mut AtlasState<F, H>,
+        brush: u32,
+        pending: Vec<OwnedSection<SectionExtra>>,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+    ) -> Result<Option<Vec<GlyphQuad>>, BrushError> {
+        let mut splits = state.splits.remove(&brush).unwrap_or_default();
+        let mut changed = false;
+
+        let mut index = 0;
+        while index <= splits.len() {
+            let range = page_range(&splits, index, pending.len());
+            if state.pages.len() <= index {
+                let max = state.max_dimension;
+                state.pages.push(state.new_page(device, (max, max)));
+                state.resizes += 1;
+                self.invalidate();
+            }
+
+            let page = &mut state.pages[index];
+            for section in &pending[range.clone()] {
+                let section = section.to_borrowed();
+                let layout = SectionExtra::layout(&section);
+                page.inner.queue_custom_layout(section, &layout);
+            }
+            if index == 0 {
+                queue_hidden(&mut page.inner, state.pinned.values().cloned().collect());
+            }
+            for (id, sections) in state.retained.iter().filter(|(id, _)| **id != brush) {
+                let other = state.splits.get(id).map_or(&[][..], Vec::as_slice);
+                for section in &sections[page_range(other, index, sections.len())] {
+                    page.inner.keep_cached(section.to_borrowed());
+                }
+            }
+
+            // The queue is kept when the glyphs don't fit, so growing the texture
+            // only has to process it again.
+            let max = state.max_dimension;
+            let brush_action = loop {
+                let (mut moved, mut uploaded) = (false, false);
+                let (width, height) = page.inner.texture_dimensions();
+                let empty = page.empty;
+                let brush_action = page.inner.process_queued(
+                    |rect, data| {
+                        moved |= is_repack(rect, width, empty);
+                        uploaded = true;
+                        uploader.write_glyph(&page.texture, rect, data);
+                    },
+                    GlyphQuad::new,
+                );
+                uploader.flush_glyphs(&page.texture);
+                if moved {
+                    self.invalidate();
+                }
+                page.empty &= !uploaded;
+
+                match brush_action {
+                    Err(glyph_brush::BrushError::TextureTooSmall { suggested })
+                        if width < max || height < max =>
+                    {
+                        // Trimmed pages grow back like a single cache texture.
+                        let (width, height) =
+                            (suggested.0.min(max), suggested.1.min(max));
+                        page.texture = create_texture(
+                            device,
+                            (width, height),
+                            state.settings.mip_levels(),
+                        );
+                        state.resizes += 1;
+                        page.inner.resize_texture(width, height);
+                        page.empty = true;
+                        self.invalidate();
+                    }
+                    brush_action => break brush_action,
+                }
+            };
+
+            match brush_action {
+                Ok(BrushAction::Draw(mut vertices)) => {
+                    for quad in &mut vertices {
+                        quad.page = index as u32;
+                    }
+                    page.vertices = vertices;
+                    changed = true;
+                }
+                Ok(BrushAction::ReDraw) => (),
+
+                Err(glyph_brush::BrushError::TextureTooSmall { .. }) => {
+                    // Starts the page over, since its queue can't be cleared.
+                    state.pages[index] = state.new_page(device, (max, max));
+                    self.invalidate();
+                    if !split_page(&mut splits, index, range) {
+                        state.splits.insert(brush, splits);
+                        return Err(BrushError::TooBigCacheTexture(state.max_dimension));
+                    }
+                    continue;
+                }
+            }
+            index += 1;
+        }
+
+        let vertices: Option<Vec<GlyphQuad>> = changed.then(|| {
+            state.pages[..=splits.len()]
+                .iter()
+                .flat_map(|page| page.vertices.iter().copied())
+                .collect()
+        });
+        if let Some(vertices) = &vertices {
+            let max = state.max_dimension;
+            state
+                .working_set
+                .insert(brush, texels(vertices, (max, max)));
+        }
+        state.splits.insert(brush, splits);
+        if state.is_shared() {
+            state.retained.insert(brush, pending);
+        }
+        Ok(vertices)
+    }
+}
+
+impl<F: Font, H: BuildHasher> AtlasState<F, H> {
+    /// Returns the cache texture dimensions, those of the first page if paged.
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self.pages.first() {
+            Some(page) => page.inner.texture_dimensions(),
+            None => self.inner.texture_dimensions(),
+        }
+    }
+
+    /// Returns the estimated fraction of the cache textures used by the last
+    /// processed frames of all brushes and the pinned glyphs.
+    pub fn occupancy(&self) -> f32 {
+        let used = self.working_set.values().sum::<u64>() + self.pinned_area;
+        let texels: u64 = self
+            .texture_dimensions()
+            .map(|(width, height)| width as u64 * height as u64)
+            .sum();
+        (used as f64 / texels.max(1) as f64).min(1.0) as f32
+    }
+
+    /// Returns the size of all cache textures in bytes, including their mip levels.
+    pub fn texture_bytes(&self) -> u64 {
+        let mip_levels = self.settings.mip_levels();
+        self.texture_dimensions()
+            .map(|dimensions| texture_size(dimensions, mip_levels))
+            .sum()
+    }
+
+    /// Returns the dimensions of the cache textures of all pages.
+    fn texture_dimensions(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let single = self
+            .pages
+            .is_empty()
+            .then(|| self.inner.texture_dimensions());
+        single.into_iter().chain(
+            self.pages
+                .iter()
+                .map(|page| page.inner.texture_dimensions()),
+        )
+    }
+
+    /// Returns `true` if brushes hand their queued sections over to the atlas when
+    /// processing, instead of queueing them into the glyph cache directly: while
+    /// the atlas is shared, since other brushes might be processed in between, and
+    /// once the glyphs might have to be spilled into pages.
+    pub fn buffers_sections(&self) -> bool {
+        let (width, height) = self.inner.texture_dimensions();
+        self.is_shared() || self.is_paged() || width.max(height) >= self.max_dimension / 4
+    }
+
+    /// Queues the `section` of a brush into the glyph cache directly, see
+    /// [`AtlasState::buffers_sections()`].
+    pub fn queue(&mut self, section: Section<SectionExtra>) {
+        self.touch(&section);
+        let layout = SectionExtra::layout(&section);
+        self.inner.queue_custom_layout(section, &layout);
+        self.queued = true;
+    }
+
+    /// Records the glyphs of the `section` as used by the current frame, if the
+    /// atlas evicts the least recently used glyphs.
+    fn touch(&mut self, section: &Section<SectionExtra>) {
+        if self.settings.eviction == EvictionPolicy::Frame || self.is_paged() {
+            return;
+        }
+        let frame = self.frame;
+        let mut new = Vec::new();
+        let layout = SectionExtra::layout(section);
+        for glyph in self.inner.glyphs_custom_layout(section, &layout) {
+            match self
+                .recent
+                .get_mut(&section_glyph_key(glyph, self.position_tolerance))
+            {
+                Some(recent) => recent.last_used = frame,
+                None => new.push(glyph.clone()),
+            }
+        }
+
+        for mut glyph in new {
+            let position = &mut glyph.glyph.position;
+            *position = point(position.x.rem_euclid(1.0), position.y.rem_euclid(1.0));
+            let area = self.glyph_area(&glyph);
+            glyph.section_index = 0;
+            glyph.byte_index = 0;
+            self.recent.insert(
+                section_glyph_key(&glyph, self.position_tolerance),
+                RecentGlyph {
+                    glyph,
+                    area,
+                    last_used: frame,
+                },
+            );
+        }
+    }
+
+    /// Queues the pinned glyphs, and the most recently used glyphs which aren't used
+    /// by the current frame, as many as fit about half of the free space of the
+    /// cache texture.
+    fn queue_kept_glyphs(&mut self, brush: u32) {
+        let mut glyphs: Vec<_> = self.pinned.values().cloned().collect();
+        if let EvictionPolicy::Lru { max_age } = self.settings.eviction {
+            let frame = self.frame;
+            self.recent
+                .retain(|_, glyph| frame - glyph.last_used <= max_age as u64);
+
+            let (width, height) = self.inner.texture_dimensions();
+            let used = self
+                .working_set
+                .iter()
+                .filter(|(id, _)| **id != brush)
+                .map(|(_, texels)| texels)
+                .sum::<u64>()
+                + self
+                    .recent
+                    .values()
+                    .filter(|glyph| glyph.last_used == frame)
+                    .map(|glyph| glyph.area)
+                    .sum::<u64>()
+                + self.pinned_area;
+            // Recently used glyphs don't grow the cached glyphs beyond the budget.
+            let mut capacity = width as u64 * height as u64;
+            if let Some(budget) = self.settings.budget {
+                let bytes = texture_size((width, height), self.settings.mip_levels());
+                capacity = capacity.min(budget.saturating_mul(capacity) / bytes);
+            }
+            let mut free = capacity.saturating_sub(used) / 2;
+
+            let mut unused: Vec<_> = self
+                .recent
+                .iter()
+                .filter(|(key, glyph)| {
+                    glyph.last_used != frame && !self.pinned.contains_key(key)
+                })
+                .collect();
+            // Ordered by key as well, so unchanged frames queue the same glyphs and
+            // can be redrawn.
+            unused.sort_unstable_by_key(|(key, glyph)| (Reverse(glyph.last_used), **key));
+            glyphs.extend(unused.into_iter().map_while(|(_, glyph)| {
+                free = free.checked_sub(glyph.area)?;
+                Some(glyph.glyph.clone())
+            }));
+        }
+        queue_hidden(&mut self.inner, glyphs);
+    }
+
+    /// Uploads the bitmaps of the stored `glyphs` which were `outlined` as
+    /// placeholders into the cache texture, at the glyph rectangles of the
+    /// `vertices` tagged with their index.
+    fn upload_stored(
+        &self,
+        glyphs: &[StoredGlyph],
+        vertices: &[GlyphQuad],
+        outlined: &HashSet<GlyphKey>,
+        uploader: &mut Uploader,
+    ) {
+        let (width, height) = self.inner.texture_dimensions();
+        let mut uploaded = HashSet::new();
+        for quad in vertices {
+            let Some(stored) = glyphs.get(quad.section as usize) else {
+                continue;
+            };
+            let key = section_glyph_key(&stored.glyph, self.position_tolerance);
+            let [left, top, ..] = quad.vertex.tex_rect();
+            // The vertices don't include the padding.
+            let min = [
+                (left * width as f32).round() as u32 - 1,
+                (top * height as f32).round() as u32 - 1,
+            ];
+            if !outlined.contains(&key) || !uploaded.insert(min) {
+                continue;
+            }
+
+            // Mipmapped glyphs are aligned to 4x4 texels, zeroed like the draw cache
+            // does, for downsampling them.
+            let (rect_width, rect_height) = if self.settings.mipmaps {
+                (
+                    stored.width.next_multiple_of(4),
+                    stored.height.next_multiple_of(4),
+                )
+            } else {
+                (stored.width, stored.height)
+            };
+            let mut texels = vec![0; rect_width as usize * rect_height as usize];
+            for (row, data) in stored
+                .texels
+                .chunks_exact(stored.width as usize)
+                .enumerate()
+            {
+                let start = row * rect_width as usize;
+                texels[start..start + data.len()].copy_from_slice(data);
+            }
+            let rect = Rectangle {
+                min,
+                max: [min[0] + rect_width, min[1] + rect_height],
+            };
+            uploader.write_glyph(&self.texture, rect, &texels);
+        }
+        uploader.flush_glyphs(&self.texture);
+    }
+
+    /// Pins the positioned `glyph`, see [`GlyphAtlas::pin()`].
+    fn pin(&mut self, glyph: SectionGlyph) {
+        let key = section_glyph_key(&glyph, self.position_tolerance);
+        if !self.pinned.contains_key(&key) {
+            self.pinned_area += self.glyph_area(&glyph);
+            self.pinned.insert(key, glyph);
+        }
+    }
+
+    /// Estimates the size of the `glyph` in the cache texture, in texels.
+    fn glyph_area(&self, glyph: &SectionGlyph) -> u64 {
+        self.inner.fonts()[glyph.font_id.0]
+            .outline_glyph(glyph.glyph.clone())
+            .map_or(0, |outlined| {
+                let bounds = outlined.px_bounds();
+                (bounds.width() as u64 + 1) * (bounds.height() as u64 + 1)
+            })
+    }
+
+    fn new_page(&self, device: &wgpu::Device, (width, height): (u32, u32)) -> Page<F> {
+        let mut builder = GlyphBrushBuilder::using_fonts(self.inner.fonts().to_vec());
+        builder.cache_glyph_positioning = self.page_builder.cache_glyph_positioning;
+        builder.cache_redraws = self.page_builder.cache_redraws;
+        builder.draw_cache_builder = self
+            .page_builder
+            .draw_cache_builder
+            .clone()
+            .dimensions(width, height);
+        let inner = builder.build();
+
+        Page {
+            texture: create_texture(
+                device,
+                inner.texture_dimensions(),
+                self.settings.mip_levels(),
+            ),
+            inner,
+            empty: true,
+            vertices: Vec::new(),
+        }
+    }
+}
+
+impl<F: Font, H: BuildHasher + Clone> AtlasState<F, H> {
+    /// Shrinks the cache textures to the working set if requested, or if they
+    /// exceed the budget. Pages are merged back into a single texture once the
+    /// working set fits a third of a texture of the maximum size.
+    ///
+    /// Returns `true` if the textures were recreated.
+    fn trim(&mut self, device: &wgpu::Device) -> bool {
+        let working_set = self.working_set.values().sum::<u64>();
+        let over_budget = self.settings.budget.is_some_and(|budget| {
+            self.texture_bytes() > budget
+                && self.trim_floor.is_none_or(|floor| working_set < floor)
+        });
+        let max = self.max_dimension as u64;
+        let merge = self.is_paged() && (working_set + self.pinned_area) * 3 <= max * max;
+        if !std::mem::take(&mut self.trim) && !over_budget && !merge {
+            return false;
+        }
+
+        let (width, height) = self.trimmed_dimensions();
+        let bytes = texture_size((width, height), self.settings.mip_levels());
+        if !merge && bytes >= self.texture_bytes() {
+            return false;
+        }
+        if over_budget {
+            self.trim_floor = Some(working_set);
+        }
+        log::debug!("Trimming the glyph cache to {width}x{height}");
+        self.resizes += 1;
+
+        if self.is_paged() {
+            // Keeps paging if the glyphs might not fit a single texture.
+            self.pages.clear();
+            self.splits.clear();
+            if !merge {
+                let page = self.new_page(device, (width, height));
+                self.pages.push(page);
+                return true;
+            }
+            log::debug!("Merging the glyph cache pages");
+            self.reset_inner((width, height));
+        } else {
+            self.inner.resize_texture(width, height);
+        }
+        self.texture =
+            create_texture(device, (width, height), self.settings.mip_levels());
+        self.empty = true;
+        true
+    }
+
+    /// Replaces `inner` with an empty glyph cache with the provided dimensions,
+    /// dropping its queue.
+    fn reset_inner(&mut self, (width, height): (u32, u32)) {
+        self.inner = self
+            .inner
+            .to_builder()
+            .initial_cache_size((width, height))
+            .build();
+        self.empty = true;
+    }
+}
+
+/// Returns `true` if the upload of the `rect` into a cache texture with the provided
+/// `width` repacks the glyphs, moving the cached ones.
+///
+/// A glyph cache which was cleared for repacking caches all glyphs into memory
+/// first and uploads them as a single rectangle spanning the whole texture width,
+/// which it otherwise only does when caching into an `empty` texture.
+fn is_repack(rect: Rectangle<u32>, width: u32, empty: bool) -> bool {
+    !empty && rect.min == [0, 0] && rect.width() == width
+}
+
+/// Queues the `glyphs` for caching without producing vertices.
+fn queue_hidden<F: Font, H: BuildHasher>(
+    brush: &mut glyph_brush::GlyphBrush<GlyphQuad, SectionExtra, F, H>,
+    glyphs: Vec<SectionGlyph>,
+) {
+    if !glyphs.is_empty() {
+        // The bounds don't contain any glyph.
+        let nowhere = Rect {
+            min: point(f32::MIN, f32::MIN),
+            max: point(f32::MIN, f32::MIN),
+        };
+        brush.queue_pre_positioned(glyphs, Vec::new(), nowhere);
+    }
+}
+
+/// Returns the key of the glyph in the draw cache. Subpixel positions are
+/// quantized like the cache does with its `position_tolerance`.
+fn glyph_key(font_id: FontId, glyph: &Glyph, position_tolerance: f32) -> GlyphKey {
+    let subpixel = |x: f32| ((x - x.round() + 0.5) / position_tolerance + 0.5) as u16;
+    (
+        font_id.0,
+        glyph.id.0,
+        glyph.scale.x.to_bits(),
+        glyph.scale.y.to_bits(),
+        subpixel(glyph.position.x),
+        subpixel(glyph.position.y),
+    )
+}
+
+/// Returns the key of the positioned glyph in the draw cache, see [`glyph_key()`].
+fn section_glyph_key(glyph: &SectionGlyph, position_tolerance: f32) -> GlyphKey {
+    glyph_key(glyph.font_id, &glyph.glyph, position_tolerance)
+}
+
+/// Returns the size of a glyph with the provided pixel bounds in the cache texture,
+/// including the padding of one texel on every side.
+/// Outlines the `glyph` of the `font` with `padding` empty texels around its pixel
+/// bounds.
+fn pad_outline<F: Font>(
+    font: &F,
+    glyph: Glyph,
+    mut outline: Outline,
+    padding: u32,
+) -> OutlinedGlyph {
+    let scale_factor = font.as_scaled(glyph.scale).scale_factor();
+    if padding > 0 {
+        // Half a texel more, so rounding the pixel bounds never loses a texel.
+        let padding = padding as f32 + 0.5;
+        let (x, y) = (
+            padding / scale_factor.horizontal,
+            padding / scale_factor.vertical,
+        );
+        outline.bounds.min = point(outline.bounds.min.x - x, outline.bounds.min.y - y);
+        outline.bounds.max = point(outline.bounds.max.x + x, outline.bounds.max.y + y);
+    }
+    OutlinedGlyph::new(glyph, outline, scale_factor)
+}
+
+fn padded_size(bounds: Rect) -> (u32, u32) {
+    (bounds.width() as u32 + 2, bounds.height() as u32 + 2)
+}
+
+/// Estimates the texels of the cache texture with the provided `dimensions` used
+/// by the `vertices`.
+fn texels(vertices: &[GlyphQuad], (width, height): (u32, u32)) -> u64 {
+    let mut glyphs = HashSet::new();
+    let area: f32 = vertices
+        .iter()
+        .filter(|quad| {
+            let [left, top, ..] = quad.vertex.tex_rect();
+            glyphs.insert((quad.page, left.to_bits(), top.to_bits()))
+        })
+        .map(|quad| {
+            let [left, top, right, bottom] = quad.vertex.tex_rect();
+            (right - left) * (bottom - top)
+        })
+        .sum();
+    (area * width as f32 * height as f32) as u64
+}
+
+/// Range of the sections cached by the page with the provided `index`.
+fn page_range(splits: &[usize], index: usize, len: usize) -> std::ops::Range<usize> {
+    let start = match index {
+        0 => 0,
+        _ => splits.get(index - 1).copied().unwrap_or(len),
+    };
+    let end = splits.get(index).copied().unwrap_or(len);
+    start.min(len)..end.min(len).max(start.min(len))
+}
+
+/// Moves the second half of the `range` of sections cached by the page with the
+/// provided `index` to the next page. Returns `false` if the page caches a single
+/// section, which can't be split.
+fn split_page(
+    splits: &mut Vec<usize>,
+    index: usize,
+    range: std::ops::Range<usize>,
+) -> bool {
+    if range.len() <= 1 {
+        return false;
+    }
+    let middle = range.start + range.len() / 2;
+    match splits.get_mut(index) {
+        Some(split) => *split = middle,
+        None => splits.push(middle),
+    }
+    true
+}
+
+impl<F, H> Clone for GlyphAtlas<F, H> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<F, H> std::fmt::Debug for GlyphAtlas<F, H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GlyphAtlas")
+            .field("fonts", &self.shared.fonts.len())
+            .field("generation", &self.generation())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the size in bytes of a cache texture with the provided dimensions and up
+/// to `mip_levels` mip levels, see [`create_texture()`].
+fn texture_size((width, height): (u32, u32), mip_levels: u32) -> u64 {
+    let texel = CACHE_FORMAT.block_copy_size(None).unwrap_or(1) as u64;
+    let levels = mip_levels.min(width.max(height).ilog2() + 1);
+    (0..levels)
+        .map(|level| (width >> level).max(1) as u64 * (height >> level).max(1) as u64)
+        .sum::<u64>()
+        * texel
+}
+
+/// Creates a cache texture with up to `mip_levels` mip levels.
+fn create_texture(
+    device: &wgpu::Device,
+    dimensions: (u32, u32),
+    mip_levels: u32,
+) -> wgpu::Texture {
+    let size = wgpu::Extent3d {
+        width: dimensions.0,
+        height: dimensions.1,
+        depth_or_array_layers: 1,
+    };
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("wgpu-text Cache Texture"),
+        size,
+        mip_level_count: mip_levels.min(dimensions.0.max(dimensions.1).ilog2() + 1),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: CACHE_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Font of the inner `GlyphBrush`, referencing the fonts shared with the brushes.
+pub(crate) struct AtlasFont<F> {
+    fonts: Arc<[F]>,
+    id: usize,
+    /// Padding of the glyph outlines, see [`CacheSettings::glyph_padding()`].
+    padding: u32,
+    placeholders: Arc<Mutex<Placeholders>>,
+}
+
+impl<F> AtlasFont<F> {
+    #[inline]
+    fn font(&self) -> &F {
+        &self.fonts[self.id]
+    }
+}
+
+impl<F> Clone for AtlasFont<F> {
+    fn clone(&self) -> Self {
+        Self {
+            fonts: self.fonts.clone(),
+            id: self.id,
+            padding: self.padding,
+            placeholders: self.placeholders.clone(),
+        }
+    }
+}
+
+impl<F: Font> Font for AtlasFont<F> {
+    #[inline]
+    fn units_per_em(&self) -> Option<f32> {
+        self.font().units_per_em()
+    }
+
+    #[inline]
+    fn ascent_unscaled(&self) -> f32 {
+        self.font().ascent_unscaled()
+    }
+
+    #[inline]
+    fn descent_unscaled(&self) -> f32 {
+        self.font().descent_unscaled()
+    }
+
+    #[inline]
+    fn line_gap_unscaled(&self) -> f32 {
+        self.font().line_gap_unscaled()
+    }
+
+    #[inline]
+    fn italic_angle(&self) -> f32 {
+        self.font().italic_angle()
+    }
+
+    #[inline]
+    fn glyph_id(&self, c: char) -> GlyphId {
+        self.font().glyph_id(c)
+    }
+
+    #[inline]
+    fn h_advance_unscaled(&self, id: GlyphId) -> f32 {
+        self.font().h_advance_unscaled(id)
+    }
+
+    #[inline]
+    fn h_side_bearing_unscaled(&self, id: GlyphId) -> f32 {
+        self.font().h_side_bearing_unscaled(id)
+    }
+
+    #[inline]
+    fn v_advance_unscaled(&self, id: GlyphId) -> f32 {
+        self.font().v_advance_unscaled(id)
+    }
+
+    #[inline]
+    fn v_side_bearing_unscaled(&self, id: GlyphId) -> f32 {
+        self.font().v_side_bearing_unscaled(id)
+    }
+
+    #[inline]
+    fn kern_unscaled(&self, first: GlyphId, second: GlyphId) -> f32 {
+        self.font().kern_unscaled(first, second)
+    }
+
+    #[inline]
+    fn outline(&self, id: GlyphId) -> Option<Outline> {
+        self.font().outline(id)
+    }
+
+    fn outline_glyph(&self, glyph: Glyph) -> Option<OutlinedGlyph> {
+        let mut outline = self.font().outline(glyph.id)?;
+        let mut placeholders = self
+            .placeholders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !placeholders.keys.is_empty() {
+            let key = glyph_key(FontId(self.id), &glyph, placeholders.position_tolerance);
+            if placeholders.keys.contains(&key) {
+                placeholders.outlined.insert(key);
+                outline.curves.clear();
+            }
+        }
+        drop(placeholders);
+        Some(pad_outline(self.font(), glyph, outline, self.padding))
+    }
+
+    #[inline]
+    fn glyph_count(&self) -> usize {
+        self.font().glyph_count()
+    }
+
+    #[inline]
+    fn codepoint_ids(&self) -> CodepointIdIter<'_> {
+        self.font().codepoint_ids()
+    }
+
+    #[inline]
+    fn glyph_raster_image2(&self, id: GlyphId, size: u16) -> Option<v2::GlyphImage<'_>> {
+        self.font().glyph_raster_image2(id, size)
+    }
+
+    #[inline]
+    fn glyph_svg_image(&self, id: GlyphId) -> Option<GlyphSvg<'_>> {
+        self.font().glyph_svg_image(id)
+    }
+
+    #[inline]
+    fn font_data(&self) -> &[u8] {
+        self.font().font_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_ranges() {
+        assert_eq!(page_range(&[], 0, 6), 0..6);
+        assert_eq!(page_range(&[], 1, 6), 6..6);
+
+        let splits = [3, 7];
+        let ranges: Vec<_> = (0..4).map(|index| page_range(&splits, index, 10)).collect();
+        assert_eq!(ranges, [0..3, 3..7, 7..10, 10..10]);
+
+        // Fewer sections than when the pages were split.
+        let ranges: Vec<_> = (0..3).map(|index| page_range(&splits, index, 5)).collect();
+        assert_eq!(ranges, [0..3, 3..5, 5..5]);
+        let ranges: Vec<_> = (0..3).map(|index| page_range(&splits, index, 2)).collect();
+        assert_eq!(ranges, [0..2, 2..2, 2..2]);
+        assert_eq!(page_range(&splits, 0, 0), 0..0);
+    }
+
+    /// Splits the page with the provided `index` of `len` sections.
+    fn split(splits: &mut Vec<usize>, index: usize, len: usize) -> bool {
+        let range = page_range(splits, index, len);
+        split_page(splits, index, range)
+    }
+
+    #[test]
+    fn page_splits() {
+        let mut splits = Vec::new();
+        assert!(split(&mut splits, 0, 10));
+        assert_eq!(splits, [5]);
+
+        // The last page gets a new split.
+        assert!(split(&mut splits, 1, 10));
+        assert_eq!(splits, [5, 7]);
+
+        // Splitting an earlier page moves its second half to the next page.
+        assert!(split(&mut splits, 0, 10));
+        assert_eq!(splits, [2, 7]);
+        let ranges: Vec<_> = (0..3).map(|index| page_range(&splits, index, 10)).collect();
+        assert_eq!(ranges, [0..2, 2..7, 7..10]);
+
+        // Single sections can't be split.
+        let mut splits = vec![1, 2];
+        assert!(!split(&mut splits, 1, 3));
+        assert!(!split(&mut splits, 2, 2));
+        assert_eq!(splits, [1, 2]);
+    }
+}
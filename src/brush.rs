@@ -1,27 +1,58 @@
-use std::num::NonZeroU32;
+use std::{borrow::Cow, num::NonZeroU32};
 
 use crate::{
     Matrix,
-    error::BrushError,
-    pipeline::{Pipeline, Vertex},
+    atlas::{CacheSettings, EvictionPolicy, GlyphAtlas},
+    cache,
+    debug::{self, AtlasImage, DebugOverlay},
+    error::{AtlasFileError, BrushError},
+    glyph::{GlyphInfo, GlyphTransform},
+    grid::{GlyphPositioning, PixelGrid},
+    persist,
+    pipeline::{Pipeline, QueuedSection, VertexAllocation},
+    shader,
+    stats::BrushStats,
+    style::{SectionExtra, SectionStyle, StyleUniforms, StyledSection},
+    upload::{UploadMode, Uploader},
 };
 use glyph_brush::{
-    BrushAction, DefaultSectionHasher, Extra, GlyphCruncher, Section, SectionGlyphIter,
-    ab_glyph::{Font, FontArc, FontRef, InvalidFont, Rect},
+    DefaultSectionHasher, FontId, GlyphCruncher, OwnedSection, Section, SectionGlyph,
+    SectionGlyphIter,
+    ab_glyph::{Font, FontArc, FontRef, InvalidFont, Point, PxScale, Rect, point},
 };
 
 /// Wrapper over [`glyph_brush::GlyphBrush`]. In charge of drawing text.
 ///
 /// Used for queuing and rendering text with [`TextBrush::draw`].
 pub struct TextBrush<F = FontArc, H = DefaultSectionHasher> {
-    inner: glyph_brush::GlyphBrush<Vertex, Extra, F, H>,
+    atlas: GlyphAtlas<F, H>,
+    /// Identifier of the brush within its atlas.
+    id: u32,
+    /// Atlas generation the vertices were processed in.
+    generation: u64,
     pipeline: Pipeline,
+    /// Styles of the sections queued this frame. The first one is the default style.
+    styles: Vec<StyleUniforms>,
+    /// Sections queued since the last processing.
+    sections: Vec<QueuedSection>,
+    /// Sections queued since the last processing, while the atlas buffers them.
+    pending: Vec<OwnedSection<SectionExtra>>,
+    /// Glyphs returned by the last `glyphs_iter` call.
+    glyphs: Vec<SectionGlyph>,
+    /// Staging buffers used with [`UploadMode::StagingBelt`].
+    belt: Option<wgpu::util::StagingBelt>,
+    /// Statistics of the last processing.
+    stats: BrushStats,
+    /// Alignment of the glyphs to the device pixel grid.
+    grid: PixelGrid,
+    /// Device pixels per logical pixel of the sections.
+    scale_factor: f32,
 }
 
 impl<F, H> TextBrush<F, H>
 where
-    F: Font + Sync,
-    H: std::hash::BuildHasher,
+    F: Font + Send + Sync,
+    H: std::hash::BuildHasher + Clone,
 {
     /// Queues section for drawing, processes all queued text and updates the
     /// inner vertex buffer, unless the text vertices remain unmodified when
@@ -30,11 +61,21 @@ where
     /// If utilizing *depth*, the `sections` list should have `Section`s ordered from
     /// furthest to closest. They will be drawn in the order they are given.
     ///
+    /// Besides plain sections, `(section, style)` tuples and [`StyledSection`]s can
+    /// be queued for applying a [`SectionStyle`] to a section.
+    ///
+    /// Sections with a different [`SectionStyle::layer`] can be drawn separately with
+    /// [`draw_layer`](#method.draw_layer), while keeping their relative order
+    /// within a layer.
+    ///
     /// - This method should be called every frame.
     ///
     /// If not called when required, the draw functions will continue drawing data from the
     /// inner vertex buffer meaning they will redraw old vertices.
     ///
+    /// Same as calling [`queue_section`](#method.queue_section) for each section
+    /// followed by [`process`](#method.process).
+    ///
     /// To learn about GPU texture caching, see
     /// [`caching behaviour`](https://docs.rs/glyph_brush/latest/glyph_brush/struct.GlyphBrush.html#caching-behaviour)
     #[inline]
@@ -45,96 +86,581 @@ where
         sections: I,
     ) -> Result<(), BrushError>
     where
-        S: Into<std::borrow::Cow<'a, Section<'a>>>,
+        S: Into<StyledSection<'a>>,
     {
-        // Queue sections:
-        for s in sections {
-            self.inner.queue(s);
+        for section in sections {
+            self.queue_section(section);
         }
+        self.process(device, queue)
+    }
 
-        // Process sections:
-        loop {
-            // Contains BrushAction enum which marks for
-            // drawing or redrawing (using old data).
-            let brush_action = self.inner.process_queued(
-                |rect, data| self.pipeline.update_texture(rect, data, queue),
-                Vertex::to_vertex,
-            );
+    /// Queues a single section for drawing without processing it.
+    ///
+    /// Can be called any number of times per frame, for example from independent
+    /// systems, before a single [`process`](#method.process) call. Sections are drawn
+    /// in the order they were queued.
+    #[inline]
+    pub fn queue_section<'a, S>(&mut self, section: S)
+    where
+        S: Into<StyledSection<'a>>,
+    {
+        let StyledSection { section, style } = section.into();
+        let layer = style.layer;
+        let style = if style == SectionStyle::default().with_layer(layer) {
+            0
+        } else {
+            self.styles
+                .push(style.to_uniforms(section.screen_position, self.scale_factor));
+            self.styles.len() as u32 - 1
+        };
+        let section = SectionExtra::section(&section, self.grid, self.scale_factor);
+        self.sections.push(QueuedSection {
+            key: SectionExtra::key(&section),
+            style,
+            layer,
+        });
+        let mut atlas = self.atlas.lock();
+        if atlas.buffers_sections() {
+            self.pending.push(section.to_owned());
+        } else {
+            atlas.queue(section);
+        }
+    }
 
-            match brush_action {
-                Ok(action) => {
-                    break match action {
-                        BrushAction::Draw(vertices) => {
-                            self.pipeline.update_vertex_buffer(vertices, device, queue)
-                        }
-                        BrushAction::ReDraw => (),
-                    };
-                }
+    /// Processes all sections queued with [`queue_section`](#method.queue_section)
+    /// since the last call and updates the inner vertex buffer, unless the text
+    /// vertices remain unmodified when compared to the last frame.
+    ///
+    /// - This method should be called once every frame, before drawing.
+    pub fn process(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<(), BrushError> {
+        if self.belt.is_none() {
+            return self.process_uploads(device, queue, &mut Uploader::queue(queue));
+        }
 
-                Err(glyph_brush::BrushError::TextureTooSmall { suggested }) => {
-                    if log::log_enabled!(log::Level::Warn) {
-                        log::warn!(
-                            "Resizing cache texture! This should be avoided \
-                            by building TextBrush with BrushBuilder::initial_cache_size() \
-                            and providing bigger cache texture dimensions."
-                        );
-                    }
-                    // Texture resizing:
-                    let max_image_dimension = device.limits().max_texture_dimension_2d;
-                    let (width, height) = if suggested.0 > max_image_dimension
-                        || suggested.1 > max_image_dimension
-                    {
-                        if self.inner.texture_dimensions().0 < max_image_dimension
-                            || self.inner.texture_dimensions().1 < max_image_dimension
-                        {
-                            (max_image_dimension, max_image_dimension)
-                        } else {
-                            return Err(BrushError::TooBigCacheTexture(
-                                max_image_dimension,
-                            ));
-                        }
-                    } else {
-                        suggested
-                    };
-                    self.pipeline.resize_texture(device, (width, height));
-                    self.inner.resize_texture(width, height);
-                }
-            }
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("wgpu-text Upload Encoder"),
+            });
+        let result = self.process_with_encoder(device, queue, &mut encoder);
+        queue.submit([encoder.finish()]);
+        result
+    }
+
+    /// Same as [`process`](#method.process), but records the uploads into the
+    /// provided `encoder` when the brush was built with [`UploadMode::StagingBelt`].
+    ///
+    /// The `encoder` has to be submitted before drawing the processed text. With
+    /// [`UploadMode::Queue`], the uploads are written through the `queue` and the
+    /// encoder is left untouched.
+    pub fn process_with_encoder(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), BrushError> {
+        let Some(mut belt) = self.belt.take() else {
+            return self.process_uploads(device, queue, &mut Uploader::queue(queue));
+        };
+
+        let result = self.process_uploads(
+            device,
+            queue,
+            &mut Uploader::staging_belt(&mut belt, encoder),
+        );
+        belt.finish_and_recall_on_submit(encoder);
+        self.belt = Some(belt);
+        result
+    }
+
+    fn process_uploads(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        uploader: &mut Uploader,
+    ) -> Result<(), BrushError> {
+        self.pipeline.update_styles(&self.styles, device, queue);
+        self.styles.truncate(1);
+        let sections = std::mem::take(&mut self.sections);
+        let sections_queued = sections.len() as u32;
+        let resizes = self.atlas.lock().resizes;
+        let reallocations = self.pipeline.vertex_reallocations();
+        self.pipeline.reset_draw_stats();
+
+        let pending = std::mem::take(&mut self.pending);
+        let processed = self.atlas.process(self.id, pending, device, uploader)?;
+        let redraw = processed.is_none();
+        match processed {
+            Some(quads) => self
+                .pipeline
+                .update_vertex_buffer(quads, sections, device, uploader),
+            None => self.pipeline.update_sections(sections, device, uploader),
         }
+
+        let generation = self.atlas.generation();
+        if generation != self.generation {
+            let textures = self.atlas.lock().textures();
+            self.pipeline.set_textures(device, &textures);
+            self.generation = generation;
+        }
+
+        self.stats = BrushStats {
+            sections_queued,
+            glyphs_queued: self.pipeline.glyph_vertices().len() as u32,
+            redraw,
+            glyphs_uploaded: uploader.glyphs,
+            bytes_uploaded: uploader.bytes,
+            texture_resizes: (self.atlas.lock().resizes - resizes) as u32,
+            vertex_reallocations: (self.pipeline.vertex_reallocations() - reallocations)
+                as u32,
+            ..BrushStats::default()
+        };
         Ok(())
     }
 
+    /// Returns the statistics of the last processed frame, together with the draw
+    /// calls issued since then and the current state of the atlas.
+    pub fn stats(&self) -> BrushStats {
+        let (instances_drawn, draw_calls) = self.pipeline.draw_stats();
+        let state = self.atlas.lock();
+        BrushStats {
+            instances_drawn,
+            draw_calls,
+            atlas_dimensions: state.dimensions(),
+            atlas_pages: state.page_count(),
+            atlas_bytes: state.texture_bytes(),
+            atlas_occupancy: state.occupancy(),
+            ..self.stats
+        }
+    }
+
+    /// Returns `true` if the glyph atlas was rebuilt by another brush sharing it,
+    /// after this brush was processed. The brush has to be queued and processed
+    /// again, otherwise it draws outdated glyphs.
+    ///
+    /// Read about [resizing](GlyphAtlas#resizing) shared atlases for more info.
+    #[inline]
+    pub fn is_outdated(&self) -> bool {
+        self.generation != self.atlas.generation()
+    }
+
+    /// Rasterizes and uploads the glyphs of the `chars` in every font and scale ahead
+    /// of time, for example during a loading screen, so the first frame using them
+    /// doesn't have to. The glyphs are pinned, later processings of the brushes
+    /// using the atlas don't evict them until [`unpin_glyphs`](Self::unpin_glyphs)
+    /// is called. With multiple atlas [pages](GlyphAtlas#pages), only the first page
+    /// keeps them.
+    ///
+    /// Glyphs are cached for each subpixel offset, so text using them has to be
+    /// laid out at the same subpixel offsets, which is the case for the first line
+    /// of sections at integer screen positions in device pixels. The `scales` are in
+    /// logical pixels, like the sections. Pinned glyphs count towards the size of the
+    /// cache texture: unless [pixel aligned](GlyphPositioning::PixelAligned), every
+    /// glyph is cached at each of the `1 / tolerance + 1` horizontal subpixel offsets
+    /// the position tolerance of the cache distinguishes, 11 copies with the default
+    /// tolerance of `0.1`. A bigger
+    /// [`draw_cache_position_tolerance`](BrushBuilder::draw_cache_position_tolerance)
+    /// pins fewer copies.
+    ///
+    /// Queued sections stay queued and the processed text is left as is, although
+    /// the brushes using the atlas become [outdated](Self::is_outdated) once a
+    /// glyph is uploaded, since that may evict their glyphs. If the brush uses the
+    /// atlas alone and sections were queued since the last processing, the glyphs
+    /// are cached by the next one instead. Fails with [`BrushError::UnknownFont`]
+    /// if one of the `fonts` doesn't exist, in which case nothing is pinned.
+    pub fn prewarm(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        fonts: &[FontId],
+        scales: &[PxScale],
+        chars: impl IntoIterator<Item = char>,
+    ) -> Result<(), BrushError> {
+        let chars: Vec<char> = chars.into_iter().collect();
+        let scales: Vec<PxScale> = scales
+            .iter()
+            .map(|scale| PxScale {
+                x: scale.x * self.scale_factor,
+                y: scale.y * self.scale_factor,
+            })
+            .collect();
+        self.atlas.pin(fonts, &scales, &chars, self.grid)?;
+        self.atlas.cache_pinned(device, &mut Uploader::queue(queue))
+    }
+
+    /// Unpins the glyphs cached with [`prewarm`](Self::prewarm), so they can be
+    /// evicted again.
+    pub fn unpin_glyphs(&self) {
+        self.atlas.unpin();
+    }
+
+    /// Writes the glyphs known to be cached by the atlas together with their bitmaps
+    /// into an atlas file, for restoring them with [`load_atlas`](Self::load_atlas)
+    /// on the next start.
+    ///
+    /// Those are the glyphs pinned with [`prewarm`](Self::prewarm), and with
+    /// [`EvictionPolicy::Lru`] the recently used glyphs. The file contains a
+    /// versioned glyph table, the hashes of the fonts and the glyph bitmaps, which
+    /// are rasterized on the CPU, so the atlas isn't read back.
+    pub fn save_atlas(&self, writer: impl std::io::Write) -> std::io::Result<()> {
+        persist::write_glyphs(
+            writer,
+            &self.atlas.font_hashes(),
+            &self.atlas.stored_glyphs(),
+        )
+    }
+
+    /// Restores the glyphs of an atlas file written by [`save_atlas`](Self::save_atlas)
+    /// by uploading their stored bitmaps into the atlas, so they don't have to be
+    /// rasterized. Like with [`prewarm`](Self::prewarm), the glyphs are pinned until
+    /// [`unpin_glyphs`](Self::unpin_glyphs) is called, queued sections stay queued
+    /// and the brushes using the atlas may become [outdated](Self::is_outdated).
+    /// With multiple atlas [pages](GlyphAtlas#pages), or if the brush uses the atlas
+    /// alone and sections were queued since the last processing, the glyphs are
+    /// rasterized again, by the next processing in the latter case.
+    ///
+    /// Files written by another format version or for other fonts are rejected, in
+    /// which case nothing is restored.
+    pub fn load_atlas(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        reader: impl std::io::Read,
+    ) -> Result<(), AtlasFileError> {
+        let glyphs = persist::read_glyphs(reader, &self.atlas.font_hashes())?;
+        if !glyphs.iter().all(|stored| self.atlas.fits_bitmap(stored)) {
+            return Err(AtlasFileError::InvalidFormat);
+        }
+        Ok(self
+            .atlas
+            .restore(&glyphs, device, &mut Uploader::queue(queue))?)
+    }
+
+    /// Shrinks the glyph cache texture to fit the glyphs of the last processed frame
+    /// of every brush using the atlas, when processing the next time. Useful once
+    /// the amount of displayed text drops, since the texture never shrinks on its
+    /// own unless a [budget](BrushBuilder::with_cache_budget) is set.
+    ///
+    /// Trimming rebuilds the atlas, so the other brushes using it become
+    /// [outdated](Self::is_outdated).
+    pub fn trim_cache(&self) {
+        self.atlas.request_trim();
+    }
+
+    /// Returns the glyph atlas of the brush. Can be cloned and provided to
+    /// [`BrushBuilder::using_atlas()`] for sharing it with other brushes.
+    #[inline]
+    pub fn atlas(&self) -> &GlyphAtlas<F, H> {
+        &self.atlas
+    }
+
+    /// Applies per-glyph transformations and colors to the glyphs processed by the
+    /// last [`process`](#method.process) call and uploads them for drawing.
+    ///
+    /// The `hook` is invoked for every glyph with its [`GlyphInfo`] and can modify the
+    /// provided [`GlyphTransform`], which starts as an identity with the text color.
+    /// Glyph layouts and caches aren't touched, so this is cheap enough for driving
+    /// animations like typewriter reveals, wavy text or per-letter fades every frame.
+    ///
+    /// The transformations stay applied until the next call of this method or until
+    /// the queued text changes. The vertices are uploaded like the processed ones,
+    /// see [`BrushBuilder::with_upload_mode()`].
+    pub fn transform_glyphs<G>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hook: G,
+    ) where
+        G: FnMut(&GlyphInfo, &mut GlyphTransform),
+    {
+        if self.belt.is_none() {
+            self.pipeline
+                .transform_glyphs(&mut Uploader::queue(queue), hook);
+            return;
+        }
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("wgpu-text Upload Encoder"),
+            });
+        self.transform_glyphs_with_encoder(queue, &mut encoder, hook);
+        queue.submit([encoder.finish()]);
+    }
+
+    /// Same as [`transform_glyphs`](#method.transform_glyphs), but records the
+    /// uploads into the provided `encoder` when the brush was built with
+    /// [`UploadMode::StagingBelt`], after the ones of
+    /// [`process_with_encoder`](#method.process_with_encoder).
+    ///
+    /// The `encoder` has to be submitted before drawing the transformed text. With
+    /// [`UploadMode::Queue`], the vertices are written through the `queue` and the
+    /// encoder is left untouched.
+    pub fn transform_glyphs_with_encoder<G>(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        hook: G,
+    ) where
+        G: FnMut(&GlyphInfo, &mut GlyphTransform),
+    {
+        let Some(mut belt) = self.belt.take() else {
+            self.pipeline
+                .transform_glyphs(&mut Uploader::queue(queue), hook);
+            return;
+        };
+
+        self.pipeline
+            .transform_glyphs(&mut Uploader::staging_belt(&mut belt, encoder), hook);
+        belt.finish_and_recall_on_submit(encoder);
+        self.belt = Some(belt);
+    }
+
     /// Returns a bounding box for the section glyphs calculated using each
     /// glyph's vertical & horizontal metrics. For more info, read about
     /// [`GlyphCruncher::glyph_bounds`].
+    ///
+    /// The bounds are in logical pixels, like the section.
     #[inline]
     pub fn glyph_bounds<'a, S>(&mut self, section: S) -> Option<Rect>
     where
-        S: Into<std::borrow::Cow<'a, Section<'a>>>,
+        S: Into<Cow<'a, Section<'a>>>,
+    {
+        let section = self.layout_section(&section.into());
+        let layout = SectionExtra::layout(&section);
+        let Rect { min, max } = self
+            .atlas
+            .lock()
+            .inner
+            .glyph_bounds_custom_layout(section, &layout)?;
+
+        Some(Rect {
+            min: point(min.x / self.scale_factor, min.y / self.scale_factor),
+            max: point(max.x / self.scale_factor, max.y / self.scale_factor),
+        })
+    }
+
+    /// Returns the bounding polygon of the section glyphs transformed by the
+    /// [`SectionTransform`](crate::SectionTransform) of the section style.
+    /// Useful for hit testing rotated or skewed sections.
+    ///
+    /// The corners are ordered as top-left, top-right, bottom-right and
+    /// bottom-left of the untransformed [`glyph_bounds`](#method.glyph_bounds).
+    pub fn transformed_glyph_bounds<'a, S>(&mut self, section: S) -> Option<[Point; 4]>
+    where
+        S: Into<StyledSection<'a>>,
     {
-        self.inner.glyph_bounds(section)
+        let StyledSection { section, style } = section.into();
+        let Rect { min, max } = self.glyph_bounds(section.as_ref())?;
+        let origin = section.screen_position;
+
+        Some(
+            [min, point(max.x, min.y), max, point(min.x, max.y)]
+                .map(|corner| style.transform.transform_point(origin, corner)),
+        )
     }
 
     /// Returns an iterator over the `PositionedGlyph`s of the given section.
+    ///
+    /// Glyph positions and scales are in logical pixels, like the section.
     #[inline]
     pub fn glyphs_iter<'a, 'b, S>(&'b mut self, section: S) -> SectionGlyphIter<'b>
     where
-        S: Into<std::borrow::Cow<'a, Section<'a>>>,
+        S: Into<Cow<'a, Section<'a>>>,
     {
-        self.inner.glyphs(section)
+        let section = self.layout_section(&section.into());
+        let layout = SectionExtra::layout(&section);
+        let scale_factor = self.scale_factor;
+        let mut atlas = self.atlas.lock();
+        self.glyphs.clear();
+        self.glyphs
+            .extend(
+                atlas
+                    .inner
+                    .glyphs_custom_layout(section, &layout)
+                    .map(|glyph| {
+                        let mut glyph = glyph.clone();
+                        let (position, scale) = (glyph.glyph.position, glyph.glyph.scale);
+                        glyph.glyph.position =
+                            point(position.x / scale_factor, position.y / scale_factor);
+                        glyph.glyph.scale = PxScale {
+                            x: scale.x / scale_factor,
+                            y: scale.y / scale_factor,
+                        };
+                        glyph
+                    }),
+            );
+        self.glyphs.iter()
+    }
+
+    /// Converts a section for measuring its layout.
+    fn layout_section<'a>(&self, section: &Section<'a>) -> Section<'a, SectionExtra> {
+        SectionExtra::section(section, self.grid, self.scale_factor)
+    }
+
+    /// Sets the number of device pixels per logical pixel, see
+    /// [`BrushBuilder::with_scale_factor()`]. Applies to the sections queued
+    /// afterwards, so it should be called before queuing the sections of a frame,
+    /// for example when the window moves to a monitor with a different scale factor.
+    ///
+    /// Glyphs are rasterized again at the new resolution. The glyphs of the old
+    /// scale factor aren't used anymore and get evicted like any other unused
+    /// glyphs. Glyphs cached with [`prewarm`](Self::prewarm) stay pinned until
+    /// [`unpin_glyphs`](Self::unpin_glyphs) is called.
+    ///
+    /// The render matrix isn't affected, since it already maps device pixels.
+    /// Factors which aren't finite and positive are ignored.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        if is_valid_scale_factor(scale_factor) {
+            self.scale_factor = scale_factor;
+        }
+    }
+
+    /// Returns the number of device pixels per logical pixel.
+    #[inline]
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
     }
 
     /// Returns the available fonts.
     ///
     /// The `FontId` corresponds to the index of the font data.
     pub fn fonts(&self) -> &[F] {
-        self.inner.fonts()
+        self.atlas.fonts()
     }
 
-    /// Draws all sections queued with [`queue`](#method.queue) function.
+    /// Draws all sections processed by the last [`process`](#method.process) call.
     #[inline]
     pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
-        self.pipeline.draw(rpass)
+        self.pipeline.draw(rpass, &DrawOptions::default())
+    }
+
+    /// Draws a debug overlay showing the glyph cache textures and outlining the quad
+    /// of every glyph drawn by the brush, useful when tuning
+    /// [`BrushBuilder::initial_cache_size()`]. See [`DebugOverlay`].
+    ///
+    /// The render pass has to draw into the target the brush was built for.
+    pub fn draw_debug(&self, rpass: &mut wgpu::RenderPass, overlay: &DebugOverlay) {
+        self.pipeline.draw_debug(rpass, overlay)
+    }
+
+    /// Reads the glyph cache texture of every atlas page back into CPU memory,
+    /// listing the rectangles of the glyphs drawn by the brush. Blocks until the
+    /// GPU finished copying, so this is meant for debugging.
+    ///
+    /// Fails with [`BrushError::AtlasReadback`] if mapping the copied texels fails,
+    /// for example after the device was lost.
+    pub fn dump_atlas(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Vec<AtlasImage>, BrushError> {
+        let textures = self.atlas.lock().textures();
+        textures
+            .iter()
+            .enumerate()
+            .map(|(page, texture)| {
+                let (width, height) = (texture.width(), texture.height());
+                Ok(AtlasImage {
+                    width,
+                    height,
+                    texels: debug::read_texture(device, queue, texture)?,
+                    glyphs: debug::glyph_rects(
+                        self.pipeline.page_vertices(page as u32),
+                        (width, height),
+                    ),
+                })
+            })
+            .collect()
+    }
+
+    /// Draws only the sections queued to the provided `layer`
+    /// ([`SectionStyle::with_layer()`]).
+    ///
+    /// Useful for drawing text both below and above other geometry in the same
+    /// render pass, while sharing one vertex buffer and glyph cache.
+    #[inline]
+    pub fn draw_layer(&self, rpass: &mut wgpu::RenderPass, layer: u32) {
+        self.pipeline
+            .draw(rpass, &DrawOptions::default().with_layer(layer))
+    }
+
+    /// Draws all sections processed by the last [`process`](#method.process) call into
+    /// a render pass with the provided `target`.
+    ///
+    /// The brush can draw into targets with a different color format, sample count
+    /// or depth stencil format than the one it was built for. The render pipeline for
+    /// each kind of target is created on first use, which can be done ahead of time
+    /// with [`prepare_target`](#method.prepare_target).
+    ///
+    /// Depth testing set up with [`BrushBuilder::with_depth_stencil()`] applies to
+    /// targets with a depth stencil attachment. Without it, text is drawn on top
+    /// without writing depth.
+    #[inline]
+    pub fn draw_to<T>(&self, rpass: &mut wgpu::RenderPass, target: T)
+    where
+        T: Into<RenderTarget>,
+    {
+        self.pipeline
+            .draw(rpass, &DrawOptions::default().with_target(target.into()))
+    }
+
+    /// Creates the render pipeline used for drawing into the provided `target`,
+    /// avoiding a hitch on the first [`draw_to`](#method.draw_to) call.
+    #[inline]
+    pub fn prepare_target<T>(&self, target: T)
+    where
+        T: Into<RenderTarget>,
+    {
+        self.pipeline.prepare_target(target.into());
+    }
+
+    /// Returns the number of glyphs (instances) the vertex buffer can currently hold
+    /// without being reallocated.
+    ///
+    /// See [`BrushBuilder::with_vertex_capacity()`].
+    #[inline]
+    pub fn vertex_capacity(&self) -> usize {
+        self.pipeline.vertex_capacity()
+    }
+
+    /// Returns how many times the vertex buffer was reallocated because it was too
+    /// small or shrunk by its [`ShrinkPolicy`].
+    #[inline]
+    pub fn vertex_reallocations(&self) -> u64 {
+        self.pipeline.vertex_reallocations()
+    }
+
+    /// Returns a render bundle drawing all sections processed by the last
+    /// [`process`](#method.process) call, as [`draw_with`](#method.draw_with) would.
+    ///
+    /// The bundle is recorded once per target and layer of `options` and reused
+    /// until processing changes the vertices or the glyph cache texture is
+    /// recreated, so a static text can be drawn with
+    /// [`wgpu::RenderPass::execute_bundles()`] without re-recording its commands
+    /// every frame. Calling this again after processing is cheap when nothing changed.
+    ///
+    /// The bind groups and immediate data of `options` are recorded together with the
+    /// bundle, which is recorded again when they differ from the last call for the
+    /// same target and layer.
+    ///
+    /// If the target has a depth stencil attachment, the bundle is recorded as
+    /// read-only for the aspects the brush doesn't write to.
+    pub fn render_bundle(&self, options: &DrawOptions) -> wgpu::RenderBundle {
+        self.pipeline.render_bundle(options)
+    }
+
+    /// Draws all sections processed by the last [`process`](#method.process) call while
+    /// binding the additional data from `options`.
+    ///
+    /// Used with custom shaders which utilize the bind group layouts and immediate
+    /// data registered with [`BrushBuilder::with_bind_group_layout()`] and
+    /// [`BrushBuilder::with_immediate_size()`].
+    #[inline]
+    pub fn draw_with(&self, rpass: &mut wgpu::RenderPass, options: &DrawOptions) {
+        self.pipeline.draw(rpass, options)
     }
 
     /// Resizes the view matrix. Updates the default orthographic view matrix with
@@ -154,9 +680,37 @@ where
     ///     ]
     /// }
     /// ```
+    ///
+    /// Also updates the viewport used by screen-sized billboards
+    /// ([`Self::update_viewport()`]).
     #[inline]
     pub fn resize_view(&self, width: f32, height: f32, queue: &wgpu::Queue) {
         self.update_matrix(crate::ortho(width, height), queue);
+        self.update_viewport(width, height, queue);
+    }
+
+    /// Updates the render target dimensions in pixels, used for sizing
+    /// [`BillboardSize::Screen`](crate::BillboardSize::Screen) billboards.
+    ///
+    /// Already done by [`Self::resize_view()`], but has to be called when
+    /// resizing while using a custom matrix.
+    #[inline]
+    pub fn update_viewport(&self, width: f32, height: f32, queue: &wgpu::Queue) {
+        self.pipeline.update_viewport((width, height), queue);
+    }
+
+    /// Updates the world space camera `right` and `up` vectors, used for orienting
+    /// [`BillboardSize::World`](crate::BillboardSize::World) billboards.
+    ///
+    /// Defaults to the `x` and `y` axes.
+    #[inline]
+    pub fn update_billboard_axes(
+        &self,
+        right: [f32; 3],
+        up: [f32; 3],
+        queue: &wgpu::Queue,
+    ) {
+        self.pipeline.update_billboard_axes(right, up, queue);
     }
 
     /// Resizes the view. Updates text rendering matrix with the provided one.
@@ -172,6 +726,139 @@ where
     {
         self.pipeline.update_matrix(matrix.into(), queue);
     }
+
+    /// Updates the luminance multiplier applied to the color of all rendered text.
+    ///
+    /// Mostly useful when rendering onto float or extended-range targets where
+    /// `1.0` doesn't correspond to the desired brightness of the text.
+    /// Read about the [color pipeline](crate#color-pipeline) for more info.
+    #[inline]
+    pub fn update_luminance(&self, luminance: f32, queue: &wgpu::Queue) {
+        self.pipeline.update_luminance(luminance, queue);
+    }
+}
+
+impl<F, H> Drop for TextBrush<F, H> {
+    fn drop(&mut self) {
+        self.atlas.unregister(self.id);
+    }
+}
+
+/// Additional data bound when drawing with [`TextBrush::draw_with()`].
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub struct DrawOptions<'a> {
+    /// Bind groups matching the layouts registered with
+    /// [`BrushBuilder::with_bind_group_layout()`], starting from group `1`.
+    pub bind_groups: &'a [&'a wgpu::BindGroup],
+    /// Immediate data written at offset `0`, sized according to
+    /// [`BrushBuilder::with_immediate_size()`]. Skipped if empty.
+    pub immediates: &'a [u8],
+    /// Draws only the sections of this layer if set, otherwise all of them.
+    pub layer: Option<u32>,
+    /// Target the render pass draws into if it differs from the one the brush
+    /// was built for.
+    pub target: Option<RenderTarget>,
+}
+
+impl<'a> DrawOptions<'a> {
+    /// Bind groups set starting from group `1`.
+    pub fn with_bind_groups(mut self, bind_groups: &'a [&'a wgpu::BindGroup]) -> Self {
+        self.bind_groups = bind_groups;
+        self
+    }
+
+    /// Immediate data written at offset `0`.
+    pub fn with_immediates(mut self, immediates: &'a [u8]) -> Self {
+        self.immediates = immediates;
+        self
+    }
+
+    /// Draws only the sections of the provided layer.
+    pub fn with_layer(mut self, layer: u32) -> Self {
+        self.layer = Some(layer);
+        self
+    }
+
+    /// Draws with the render pipeline matching the provided target.
+    pub fn with_target(mut self, target: RenderTarget) -> Self {
+        self.target = Some(target);
+        self
+    }
+}
+
+/// Description of a render pass target, used for selecting the render pipeline
+/// of a [`TextBrush`] drawing into multiple kinds of targets.
+///
+/// Pipelines for targets other than the one provided to [`BrushBuilder::build()`]
+/// are created on first use and cached, see [`TextBrush::draw_to()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RenderTarget {
+    /// Format of the color attachment.
+    pub format: wgpu::TextureFormat,
+    /// Sample count of the color attachment. Defaults to `1`.
+    pub sample_count: u32,
+    /// Format of the depth stencil attachment, if any. Defaults to `None`.
+    pub depth_format: Option<wgpu::TextureFormat>,
+}
+
+impl RenderTarget {
+    /// Creates a single sampled target of the provided `format` without
+    /// a depth stencil attachment.
+    pub fn new(format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            sample_count: 1,
+            depth_format: None,
+        }
+    }
+
+    /// Sample count of the color attachment.
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    /// Format of the depth stencil attachment.
+    pub fn with_depth_format(mut self, depth_format: wgpu::TextureFormat) -> Self {
+        self.depth_format = Some(depth_format);
+        self
+    }
+}
+
+impl From<wgpu::TextureFormat> for RenderTarget {
+    fn from(format: wgpu::TextureFormat) -> Self {
+        Self::new(format)
+    }
+}
+
+/// Policy for shrinking the vertex buffer of a [`TextBrush`] after the amount of
+/// drawn text decreases, see [`BrushBuilder::with_vertex_shrink_policy()`].
+///
+/// The buffer is shrunk when fewer than `max_usage * capacity` glyphs were
+/// processed for `frames` consecutive vertex updates. It never shrinks below the
+/// capacity provided with [`BrushBuilder::with_vertex_capacity()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShrinkPolicy {
+    /// Fraction of the capacity below which the buffer is considered underused.
+    pub max_usage: f32,
+    /// Number of consecutive underused vertex updates before shrinking.
+    pub frames: u32,
+}
+
+impl ShrinkPolicy {
+    /// Shrinks after using less than `max_usage` of the capacity for `frames`
+    /// consecutive vertex updates.
+    pub fn new(max_usage: f32, frames: u32) -> Self {
+        Self { max_usage, frames }
+    }
+}
+
+impl Default for ShrinkPolicy {
+    /// Shrinks after using less than a quarter of the buffer for `120` updates.
+    fn default() -> Self {
+        Self::new(0.25, 120)
+    }
 }
 
 /// Builder for [`TextBrush`].
@@ -182,6 +869,17 @@ pub struct BrushBuilder<F, H = DefaultSectionHasher> {
     multisample: wgpu::MultisampleState,
     multiview: Option<NonZeroU32>,
     matrix: Option<Matrix>,
+    luminance: f32,
+    shader: Cow<'static, str>,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    immediate_size: u32,
+    vertex_allocation: VertexAllocation,
+    upload_mode: UploadMode,
+    cache_settings: CacheSettings,
+    sampler: Option<wgpu::SamplerDescriptor<'static>>,
+    grid: PixelGrid,
+    scale_factor: f32,
+    atlas: Option<GlyphAtlas<F, H>>,
 }
 
 impl BrushBuilder<()> {
@@ -211,12 +909,48 @@ impl BrushBuilder<()> {
 
     /// Creates a [`BrushBuilder`] with multiple [`Font`].
     pub fn using_fonts<F: Font>(fonts: Vec<F>) -> BrushBuilder<F> {
+        BrushBuilder::new(glyph_brush::GlyphBrushBuilder::using_fonts(fonts), None)
+    }
+
+    /// Creates a [`BrushBuilder`] sharing the glyph cache texture, fonts and glyph
+    /// caches of an existing [`GlyphAtlas`], see [`TextBrush::atlas()`].
+    ///
+    /// The render format, depth stencil, matrix and other pipeline settings of the
+    /// built brush can differ from the other brushes using the atlas. Glyph cache
+    /// settings (like [`BrushBuilder::initial_cache_size()`] or
+    /// [`BrushBuilder::with_cache_budget()`]) are taken from the atlas and can't be
+    /// changed.
+    pub fn using_atlas<F, H>(atlas: &GlyphAtlas<F, H>) -> BrushBuilder<F, H>
+    where
+        F: Font,
+        H: std::hash::BuildHasher + Clone,
+    {
+        BrushBuilder::new(atlas.to_builder(), Some(atlas.clone()))
+    }
+}
+
+impl<F, H> BrushBuilder<F, H> {
+    fn new(
+        inner: glyph_brush::GlyphBrushBuilder<F, H>,
+        atlas: Option<GlyphAtlas<F, H>>,
+    ) -> Self {
         BrushBuilder {
-            inner: glyph_brush::GlyphBrushBuilder::using_fonts(fonts),
+            inner,
             depth_stencil: None,
             multisample: wgpu::MultisampleState::default(),
             multiview: None,
             matrix: None,
+            luminance: 1.0,
+            shader: Cow::Borrowed(shader::DEFAULT_SHADER),
+            bind_group_layouts: Vec::new(),
+            immediate_size: 0,
+            vertex_allocation: VertexAllocation::default(),
+            upload_mode: UploadMode::Queue,
+            cache_settings: CacheSettings::default(),
+            sampler: None,
+            grid: PixelGrid::default(),
+            scale_factor: 1.0,
+            atlas,
         }
     }
 }
@@ -240,6 +974,17 @@ where
         self
     }
 
+    /// Multiplies the RGB color components of all rendered text by `luminance`.
+    ///
+    /// To update the luminance use [`TextBrush::update_luminance()`].
+    /// Read about the [color pipeline](crate#color-pipeline) for more info.
+    ///
+    /// Defaults to `1.0`.
+    pub fn with_luminance(mut self, luminance: f32) -> Self {
+        self.luminance = luminance;
+        self
+    }
+
     /// Provide the `wgpu::MultisampleState` used by the inner pipeline.
     ///
     /// Defaults to value returned by [`wgpu::MultisampleState::default()`].
@@ -256,6 +1001,203 @@ where
         self
     }
 
+    /// Uses the provided WGSL shader module instead of the built-in one.
+    ///
+    /// The module must implement the `vs_main` vertex and the `fs_main` fragment
+    /// entry points described in [custom shaders](crate#custom-shaders).
+    /// [`DEFAULT_SHADER`](crate::DEFAULT_SHADER) is a good starting point.
+    ///
+    /// Returns [`BrushError::InvalidShader`] if the module fails validation.
+    pub fn with_shader<S>(mut self, source: S) -> Result<Self, BrushError>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.shader = shader::custom_module(source.into())?;
+        Ok(self)
+    }
+
+    /// Injects the provided WGSL `text_fragment` function into the built-in shader.
+    ///
+    /// The function replaces the default fragment coloring while the rest of
+    /// the built-in shader stays the same. Read about
+    /// [custom shaders](crate#custom-shaders) for the function signature.
+    ///
+    /// Returns [`BrushError::InvalidShader`] if the resulting shader fails validation.
+    pub fn with_fragment_shader(mut self, source: &str) -> Result<Self, BrushError> {
+        self.shader = shader::inject_fragment(source)?;
+        Ok(self)
+    }
+
+    /// Registers an additional bind group layout used by a custom shader.
+    ///
+    /// Group `0` is always occupied by the built-in bind group, so the registered
+    /// layouts are assigned to groups `1, 2, 3...` in the order they were added.
+    /// The matching bind groups are provided with [`TextBrush::draw_with()`].
+    pub fn with_bind_group_layout(mut self, layout: wgpu::BindGroupLayout) -> Self {
+        self.bind_group_layouts.push(layout);
+        self
+    }
+
+    /// Provide the size of immediate data (push constants) in bytes used by
+    /// a custom shader. The data is provided with [`TextBrush::draw_with()`].
+    ///
+    /// Requires [`wgpu::Features::IMMEDIATES`] and a big enough
+    /// [`wgpu::Limits::max_immediate_size`], otherwise building fails with
+    /// [`BrushError::UnsupportedImmediateSize`].
+    ///
+    /// Defaults to `0`.
+    pub fn with_immediate_size(mut self, size: u32) -> Self {
+        self.immediate_size = size;
+        self
+    }
+
+    /// Preallocates the vertex buffer for `capacity` glyphs, avoiding reallocations
+    /// while the amount of processed text stays below it.
+    ///
+    /// The buffer is never shrunk below this capacity. Check the current capacity
+    /// with [`TextBrush::vertex_capacity()`].
+    ///
+    /// Defaults to `0`.
+    pub fn with_vertex_capacity(mut self, capacity: usize) -> Self {
+        self.vertex_allocation.capacity = capacity;
+        self
+    }
+
+    /// Provide the factor the vertex buffer capacity is multiplied by when it's
+    /// reallocated. When more glyphs are processed than the buffer can hold, it's
+    /// reallocated to fit `growth` times as many, leaving headroom for the following
+    /// frames. Factors smaller than `1.0` are treated as `1.0` (exact fit).
+    ///
+    /// Defaults to `1.5`.
+    pub fn with_vertex_growth(mut self, growth: f32) -> Self {
+        self.vertex_allocation.growth = growth.max(1.0);
+        self
+    }
+
+    /// Shrinks the vertex buffer according to the provided [`ShrinkPolicy`] after
+    /// the amount of processed text decreases.
+    ///
+    /// By default, the vertex buffer never shrinks.
+    pub fn with_vertex_shrink_policy(mut self, policy: ShrinkPolicy) -> Self {
+        self.vertex_allocation.shrink = Some(policy);
+        self
+    }
+
+    /// Selects how vertices and glyphs are uploaded to the GPU, see [`UploadMode`].
+    ///
+    /// Defaults to [`UploadMode::Queue`].
+    pub fn with_upload_mode(mut self, mode: UploadMode) -> Self {
+        self.upload_mode = mode;
+        self
+    }
+
+    /// Limits the size of the glyph cache textures to about `bytes`.
+    ///
+    /// The textures still grow beyond the budget while the glyphs of a frame don't
+    /// fit, but are shrunk again once the processed glyphs fit a smaller texture.
+    /// With [`EvictionPolicy::Lru`], recently used glyphs are only kept while all
+    /// cached glyphs fit the budget.
+    /// Read about [eviction](GlyphAtlas#eviction) for more info.
+    ///
+    /// By default, the textures only shrink with [`TextBrush::trim_cache()`].
+    pub fn with_cache_budget(mut self, bytes: u64) -> Self {
+        self.cache_settings.budget = Some(bytes);
+        self
+    }
+
+    /// Selects which glyphs stay cached besides the ones used by the last processed
+    /// frame, see [`EvictionPolicy`].
+    ///
+    /// Defaults to [`EvictionPolicy::Frame`].
+    pub fn with_eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.cache_settings.eviction = policy;
+        self
+    }
+
+    /// Generates mipmaps for the glyph cache textures, so text drawn smaller than it
+    /// was rasterized, for example after zooming out with
+    /// [`TextBrush::update_matrix()`], doesn't shimmer.
+    ///
+    /// The textures get two additional mip levels of half and a quarter of their
+    /// size, computed on the CPU while uploading the glyphs, which adds about a third
+    /// to their size. Text drawn at less than a quarter of its size samples the
+    /// smallest level. Glyphs are aligned to blocks of 4x4 texels and padded by at
+    /// least 4 empty texels, so the smaller levels don't mix neighbouring glyphs,
+    /// which makes small glyphs take noticeably more space and their quads a few
+    /// pixels bigger.
+    /// Ignored when [using](BrushBuilder::using_atlas) an existing atlas.
+    ///
+    /// Disabled by default.
+    pub fn with_mipmaps(mut self, enabled: bool) -> Self {
+        self.cache_settings.mipmaps = enabled;
+        self
+    }
+
+    /// Samples the glyph cache textures with the provided sampler instead of the
+    /// default one, which filters linearly and clamps to the edge.
+    ///
+    /// Nearest filtering keeps bitmap-style fonts crisp when drawn at integer scale
+    /// factors, best combined with [`BrushBuilder::with_pixel_snapping()`]:
+    ///
+    /// ```
+    /// # use wgpu_text::{BrushBuilder, glyph_brush::ab_glyph::FontArc};
+    /// # fn builder(builder: BrushBuilder<FontArc>) -> BrushBuilder<FontArc> {
+    /// builder
+    ///     .with_sampler(wgpu::SamplerDescriptor {
+    ///         mag_filter: wgpu::FilterMode::Nearest,
+    ///         min_filter: wgpu::FilterMode::Nearest,
+    ///         ..Default::default()
+    ///     })
+    ///     .with_pixel_snapping(true)
+    /// # }
+    /// ```
+    pub fn with_sampler(mut self, sampler: wgpu::SamplerDescriptor<'static>) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    /// Snaps the top-left corner of every glyph quad to a whole device pixel, moving
+    /// it by at most half a pixel. Cached glyphs are reused for slightly different
+    /// subpixel positions, which otherwise places their quads between pixels and
+    /// distorts glyphs drawn with nearest filtering. See
+    /// [`BrushBuilder::with_scale_factor()`] for the size of the device pixels.
+    ///
+    /// Disabled by default.
+    pub fn with_pixel_snapping(mut self, enabled: bool) -> Self {
+        self.grid.snap_quads = enabled;
+        self
+    }
+
+    /// Aligns the glyphs to the device pixel grid before they are rasterized, see
+    /// [`GlyphPositioning`]. Small text gets sharper when its baseline snaps to the
+    /// grid, and pixel aligned glyphs also need less space in the glyph cache.
+    ///
+    /// Defaults to [`GlyphPositioning::Subpixel`].
+    pub fn with_glyph_positioning(mut self, positioning: GlyphPositioning) -> Self {
+        self.grid.positioning = positioning;
+        self
+    }
+
+    /// Sets the number of device pixels per logical pixel, usually the scale factor
+    /// of the window. Can be changed later with [`TextBrush::set_scale_factor()`].
+    ///
+    /// Sections (positions, bounds and text scales) and section styles are then
+    /// specified in logical pixels. The brush lays out and rasterizes the glyphs in
+    /// device pixels, so text stays sharp on high DPI monitors, and aligns them to
+    /// the device pixel grid (see [`BrushBuilder::with_glyph_positioning()`]). The
+    /// render matrix and dimensions (like the ones passed to
+    /// [`TextBrush::resize_view()`]) stay in device pixels, as does the hook of
+    /// [`TextBrush::transform_glyphs()`]. Factors which aren't finite and positive
+    /// are ignored.
+    ///
+    /// Defaults to `1.0`.
+    pub fn with_scale_factor(mut self, scale_factor: f32) -> Self {
+        if is_valid_scale_factor(scale_factor) {
+            self.scale_factor = scale_factor;
+        }
+        self
+    }
+
     /// Provide the *depth_stencil* if you are planning to utilize depth testing.
     ///
     /// For each section, depth can be set by modifying the z coordinate
@@ -276,6 +1218,11 @@ where
     ///
     /// If you are drawing a basic UI, you'd most likely want to be using
     /// [`wgpu::SurfaceConfiguration`]'s dimensions and texture format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a custom shader or the immediate size doesn't fit the `device` or
+    /// the brush, see [`BrushBuilder::try_build()`].
     pub fn build(
         self,
         device: &wgpu::Device,
@@ -283,7 +1230,51 @@ where
         render_height: u32,
         render_format: wgpu::TextureFormat,
     ) -> TextBrush<F, H> {
-        let inner = self.inner.build();
+        self.try_build(device, render_width, render_height, render_format)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Builds a [`TextBrush`] like [`BrushBuilder::build()`], but reports problems
+    /// instead of panicking.
+    ///
+    /// Returns [`BrushError::UnsupportedImmediateSize`] if the
+    /// [immediate size](BrushBuilder::with_immediate_size) isn't supported by the
+    /// `device`, and [`BrushError::InvalidShader`] if the shader uses features the
+    /// `device` doesn't have enabled, or its bindings, immediate data or entry
+    /// point inputs and outputs don't match the bind group layouts, immediate size
+    /// and vertex layout of the brush.
+    pub fn try_build(
+        self,
+        device: &wgpu::Device,
+        render_width: u32,
+        render_height: u32,
+        render_format: wgpu::TextureFormat,
+    ) -> Result<TextBrush<F, H>, BrushError> {
+        if self.immediate_size > 0 {
+            let limit = if device.features().contains(wgpu::Features::IMMEDIATES) {
+                device.limits().max_immediate_size
+            } else {
+                0
+            };
+            if self.immediate_size > limit {
+                return Err(BrushError::UnsupportedImmediateSize(limit));
+            }
+        }
+        shader::check_pipeline(
+            &self.shader,
+            device,
+            self.bind_group_layouts.len(),
+            self.immediate_size,
+        )?;
+
+        let atlas = self
+            .atlas
+            .unwrap_or_else(|| GlyphAtlas::new(device, self.inner, self.cache_settings));
+        let id = atlas.register();
+        let textures = atlas.lock().textures();
+        let sampler = self.sampler.unwrap_or_else(|| {
+            cache::sampler_descriptor(textures[0].mip_level_count() > 1)
+        });
 
         let matrix = self
             .matrix
@@ -295,10 +1286,45 @@ where
             self.depth_stencil,
             self.multisample,
             self.multiview,
-            inner.texture_dimensions(),
+            &textures,
+            &sampler,
             matrix,
+            self.luminance,
+            (render_width as f32, render_height as f32),
+            &self.shader,
+            &self.bind_group_layouts,
+            self.immediate_size,
+            self.vertex_allocation,
         );
 
-        TextBrush { inner, pipeline }
+        Ok(TextBrush {
+            generation: atlas.generation(),
+            atlas,
+            id,
+            pipeline,
+            styles: vec![SectionStyle::default().to_uniforms((0.0, 0.0), 1.0)],
+            sections: Vec::new(),
+            stats: BrushStats::default(),
+            grid: self.grid,
+            scale_factor: self.scale_factor,
+            pending: Vec::new(),
+            glyphs: Vec::new(),
+            belt: match self.upload_mode {
+                UploadMode::Queue => None,
+                UploadMode::StagingBelt { chunk_size } => {
+                    Some(wgpu::util::StagingBelt::new(device.clone(), chunk_size))
+                }
+            },
+        })
+    }
+}
+
+/// Returns `true` if the `scale_factor` is finite and positive, logging a warning
+/// otherwise.
+fn is_valid_scale_factor(scale_factor: f32) -> bool {
+    let valid = scale_factor.is_finite() && scale_factor > 0.0;
+    if !valid {
+        log::warn!("Ignoring the invalid scale factor {scale_factor}.");
     }
+    valid
 }
@@ -1,55 +1,102 @@
-use glyph_brush::Rectangle;
 use wgpu::util::DeviceExt;
 
-use crate::Matrix;
+use crate::{Matrix, style::StyleUniforms};
 
-/// Responsible for texture caching and the global matrix.
+/// Number of section styles the style buffer initially has room for.
+const INITIAL_STYLE_CAPACITY: usize = 16;
+
+/// Uniform data shared by all text drawn with one brush.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct Uniforms {
+    matrix: Matrix,
+    luminance: f32,
+    _padding: f32,
+    viewport: [f32; 2],
+    billboard_right: [f32; 4],
+    billboard_up: [f32; 4],
+}
+
+/// Returns the default sampler of the glyph cache textures, interpolating between
+/// the mip levels of `mipmapped` textures.
+pub fn sampler_descriptor(mipmapped: bool) -> wgpu::SamplerDescriptor<'static> {
+    wgpu::SamplerDescriptor {
+        label: Some("wgpu-text Cache Texture Sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: if mipmapped {
+            wgpu::MipmapFilterMode::Linear
+        } else {
+            wgpu::MipmapFilterMode::Nearest
+        },
+        ..Default::default()
+    }
+}
+
+/// Responsible for binding the glyph cache textures, global uniforms and section styles.
 #[derive(Debug)]
 pub struct Cache {
     pub bind_group_layout: wgpu::BindGroupLayout,
-    pub bind_group: wgpu::BindGroup,
+    /// Bind group of each glyph atlas page.
+    pub bind_groups: Vec<wgpu::BindGroup>,
 
-    matrix_buffer: wgpu::Buffer,
-    texture: wgpu::Texture,
+    uniform_buffer: wgpu::Buffer,
+    textures: Vec<wgpu::Texture>,
     sampler: wgpu::Sampler,
+
+    style_buffer: wgpu::Buffer,
+    style_capacity: usize,
+    style_stride: usize,
 }
 
 impl Cache {
     pub fn new(
         device: &wgpu::Device,
-        tex_dimensions: (u32, u32),
+        textures: &[wgpu::Texture],
+        sampler: &wgpu::SamplerDescriptor,
         matrix: Matrix,
+        luminance: f32,
+        viewport: (f32, f32),
     ) -> Self {
-        let texture = Self::create_cache_texture(device, tex_dimensions);
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("wgpu-text Cache Texture Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            ..Default::default()
-        });
-
-        let matrix_buffer =
+        let sampler = device.create_sampler(sampler);
+
+        let uniforms = Uniforms {
+            matrix,
+            luminance,
+            _padding: 0.0,
+            viewport: [viewport.0, viewport.1],
+            billboard_right: [1.0, 0.0, 0.0, 0.0],
+            billboard_up: [0.0, 1.0, 0.0, 0.0],
+        };
+        let uniform_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("wgpu-text Matrix Buffer"),
-                contents: bytemuck::cast_slice(&matrix),
+                label: Some("wgpu-text Uniform Buffer"),
+                contents: bytemuck::bytes_of(&uniforms),
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
 
+        // Each style has to start at an offset aligned for dynamic binding.
+        let style_stride = std::mem::size_of::<StyleUniforms>().next_multiple_of(
+            device.limits().min_uniform_buffer_offset_alignment as usize,
+        );
+        let style_buffer =
+            Self::create_style_buffer(device, INITIAL_STYLE_CAPACITY, style_stride);
+
         let bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("wgpu-text Matrix, Texture and Sampler Bind Group Layout"),
+                label: Some("wgpu-text Uniform, Texture and Sampler Bind Group Layout"),
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
                             min_binding_size: std::num::NonZeroU64::new(
-                                std::mem::size_of::<Matrix>() as wgpu::BufferAddress,
+                                std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
                             ),
                         },
                         count: None,
@@ -74,117 +121,185 @@ impl Cache {
                         ),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: std::num::NonZeroU64::new(
+                                std::mem::size_of::<StyleUniforms>()
+                                    as wgpu::BufferAddress,
+                            ),
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("wgpu-text Bind Group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: matrix_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(
-                        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-        });
-
-        Self {
-            matrix_buffer,
-            texture,
+        let mut cache = Self {
+            uniform_buffer,
+            textures: textures.to_vec(),
             sampler,
-            bind_group,
+            bind_groups: Vec::new(),
             bind_group_layout,
-        }
+
+            style_buffer,
+            style_capacity: INITIAL_STYLE_CAPACITY,
+            style_stride,
+        };
+        cache.recreate_bind_groups(device);
+        cache
     }
 
-    pub fn recreate_texture(
+    /// Binds the provided glyph cache textures, one for each atlas page.
+    pub fn set_textures(&mut self, device: &wgpu::Device, textures: &[wgpu::Texture]) {
+        self.textures = textures.to_vec();
+        self.recreate_bind_groups(device);
+    }
+
+    pub fn update_matrix(&self, matrix: Matrix, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&matrix));
+    }
+
+    pub fn update_luminance(&self, luminance: f32, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            std::mem::offset_of!(Uniforms, luminance) as wgpu::BufferAddress,
+            bytemuck::bytes_of(&luminance),
+        );
+    }
+
+    pub fn update_viewport(&self, viewport: (f32, f32), queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            std::mem::offset_of!(Uniforms, viewport) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[viewport.0, viewport.1]),
+        );
+    }
+
+    pub fn update_billboard_axes(
+        &self,
+        right: [f32; 3],
+        up: [f32; 3],
+        queue: &wgpu::Queue,
+    ) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            std::mem::offset_of!(Uniforms, billboard_right) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[
+                right[0], right[1], right[2], 0.0, up[0], up[1], up[2], 0.0,
+            ]),
+        );
+    }
+
+    /// Uploads the section styles, each to its own dynamic offset.
+    ///
+    /// Returns `true` if the bind groups had to be recreated.
+    pub fn update_styles(
         &mut self,
+        styles: &[StyleUniforms],
         device: &wgpu::Device,
-        tex_dimensions: (u32, u32),
-    ) {
-        self.texture = Self::create_cache_texture(device, tex_dimensions);
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        queue: &wgpu::Queue,
+    ) -> bool {
+        let grown = styles.len() > self.style_capacity;
+        if grown {
+            self.style_capacity = styles.len().next_power_of_two();
+            self.style_buffer =
+                Self::create_style_buffer(device, self.style_capacity, self.style_stride);
+            self.recreate_bind_groups(device);
+        }
+
+        let mut data = vec![0; styles.len() * self.style_stride];
+        for (chunk, style) in data.chunks_exact_mut(self.style_stride).zip(styles) {
+            chunk[..std::mem::size_of::<StyleUniforms>()]
+                .copy_from_slice(bytemuck::bytes_of(style));
+        }
+        queue.write_buffer(&self.style_buffer, 0, &data);
+        grown
+    }
+
+    /// Returns the dimensions of the bound texture of each page.
+    pub fn texture_dimensions(&self) -> Vec<(u32, u32)> {
+        self.textures
+            .iter()
+            .map(|texture| (texture.width(), texture.height()))
+            .collect()
+    }
+
+    /// Dynamic offset of the style with the provided index.
+    #[inline]
+    pub fn style_offset(&self, style: u32) -> wgpu::DynamicOffset {
+        style * self.style_stride as wgpu::DynamicOffset
+    }
+
+    fn recreate_bind_groups(&mut self, device: &wgpu::Device) {
+        self.bind_groups = self
+            .textures
+            .iter()
+            .map(|texture| {
+                Self::create_bind_group(
+                    device,
+                    &self.bind_group_layout,
+                    &self.uniform_buffer,
+                    texture,
+                    &self.sampler,
+                    &self.style_buffer,
+                )
+            })
+            .collect();
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        texture: &wgpu::Texture,
+        sampler: &wgpu::Sampler,
+        style_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("wgpu-text Bind Group"),
-            layout: &self.bind_group_layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: self.matrix_buffer.as_entire_binding(),
+                    resource: uniform_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
                     resource: wgpu::BindingResource::TextureView(
-                        &self
-                            .texture
-                            .create_view(&wgpu::TextureViewDescriptor::default()),
+                        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
                     ),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
-            ],
-        });
-    }
-
-    pub fn update_matrix(&self, matrix: Matrix, queue: &wgpu::Queue) {
-        queue.write_buffer(&self.matrix_buffer, 0, bytemuck::cast_slice(&matrix));
-    }
-
-    pub fn update_texture(&self, size: Rectangle<u32>, data: &[u8], queue: &wgpu::Queue) {
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d {
-                    x: size.min[0],
-                    y: size.min[1],
-                    z: 0,
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: style_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(
+                            std::mem::size_of::<StyleUniforms>() as wgpu::BufferAddress
+                        ),
+                    }),
                 },
-                aspect: wgpu::TextureAspect::All,
-            },
-            data,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(size.width()),
-                rows_per_image: Some(size.height()),
-            },
-            wgpu::Extent3d {
-                width: size.width(),
-                height: size.height(),
-                depth_or_array_layers: 1,
-            },
-        )
+            ],
+        })
     }
 
-    fn create_cache_texture(
+    fn create_style_buffer(
         device: &wgpu::Device,
-        dimensions: (u32, u32),
-    ) -> wgpu::Texture {
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-        device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("wgpu-text Cache Texture"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::R8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
+        capacity: usize,
+        stride: usize,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("wgpu-text Style Buffer"),
+            size: (capacity * stride) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         })
     }
 }
@@ -0,0 +1,242 @@
+use std::{collections::HashSet, io, sync::mpsc};
+
+use glyph_brush::Rectangle;
+
+use crate::{BrushError, RenderTarget, pipeline::Vertex, shader::DEBUG_SHADER};
+
+/// CPU copy of a glyph cache texture, see [`TextBrush::dump_atlas()`](crate::TextBrush::dump_atlas).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasImage {
+    pub width: u32,
+    pub height: u32,
+    /// Glyph coverage of every texel, row by row.
+    pub texels: Vec<u8>,
+    /// Rectangles of the glyphs drawn by the brush from this page, in texels.
+    pub glyphs: Vec<Rectangle<u32>>,
+}
+
+impl AtlasImage {
+    /// Writes the texels as a binary PGM image, readable by most image viewers.
+    pub fn write_pgm(&self, mut writer: impl io::Write) -> io::Result<()> {
+        write!(writer, "P5\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.texels)?;
+        writer.flush()
+    }
+}
+
+/// Settings of the overlay drawn with [`TextBrush::draw_debug()`](crate::TextBrush::draw_debug).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugOverlay {
+    /// Draws the cache texture of every atlas page, side by side.
+    pub atlas: bool,
+    /// Top left corner of the first page, in the coordinates of the brush matrix.
+    pub atlas_position: (f32, f32),
+    /// Size of a texel of the drawn pages, `1.0` by default.
+    pub atlas_scale: f32,
+    /// Color behind the glyphs of the drawn pages, which are drawn white.
+    pub atlas_background: [f32; 4],
+    /// Outlines the quad of every glyph drawn by the brush.
+    pub outlines: bool,
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        Self {
+            atlas: true,
+            atlas_position: (0.0, 0.0),
+            atlas_scale: 1.0,
+            atlas_background: [0.0, 0.0, 0.0, 0.6],
+            outlines: true,
+        }
+    }
+}
+
+impl DebugOverlay {
+    /// Returns a quad for each page with the provided dimensions.
+    pub(crate) fn atlas_quads(&self, pages: &[(u32, u32)]) -> Vec<Vertex> {
+        let (mut x, y) = self.atlas_position;
+        pages
+            .iter()
+            .map(|&(width, height)| {
+                let size = [
+                    width as f32 * self.atlas_scale,
+                    height as f32 * self.atlas_scale,
+                ];
+                let quad = Vertex::quad([x, y], size, self.atlas_background);
+                // Leaves a gap of a few texels between the pages.
+                x += size[0] + 4.0 * self.atlas_scale;
+                quad
+            })
+            .collect()
+    }
+}
+
+/// Render pipelines of the debug overlay, drawing into the target of the brush.
+#[derive(Debug)]
+pub(crate) struct DebugPipelines {
+    pub atlas: wgpu::RenderPipeline,
+    pub outlines: wgpu::RenderPipeline,
+    /// Quad corners in line strip order.
+    pub indices: wgpu::Buffer,
+}
+
+impl DebugPipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        target: RenderTarget,
+        multisample: wgpu::MultisampleState,
+        multiview_mask: Option<std::num::NonZeroU32>,
+    ) -> Self {
+        use wgpu::util::DeviceExt;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("wgpu-text Debug Shader"),
+            source: wgpu::ShaderSource::Wgsl(DEBUG_SHADER.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("wgpu-text Debug Pipeline Layout"),
+            bind_group_layouts: &[Some(bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let create = |topology, entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("wgpu-text Debug Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[Some(Vertex::buffer_layout())],
+                    compilation_options: Default::default(),
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    strip_index_format: Some(wgpu::IndexFormat::Uint16),
+                    ..Default::default()
+                },
+                // Drawn on top of everything.
+                depth_stencil: target.depth_format.map(|format| {
+                    wgpu::DepthStencilState {
+                        format,
+                        depth_write_enabled: Some(false),
+                        depth_compare: Some(wgpu::CompareFunction::Always),
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: target.sample_count,
+                    ..multisample
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: target.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                cache: None,
+                multiview_mask,
+            })
+        };
+
+        Self {
+            atlas: create(wgpu::PrimitiveTopology::TriangleStrip, "fs_main"),
+            outlines: create(wgpu::PrimitiveTopology::LineStrip, "fs_outline"),
+            indices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("wgpu-text Debug Index Buffer"),
+                contents: bytemuck::cast_slice::<u16, _>(&[0, 1, 3, 2, 0]),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+        }
+    }
+}
+
+/// Returns the rectangles of the glyphs drawn with the `vertices` from a page with
+/// the provided dimensions, in texels.
+pub(crate) fn glyph_rects<'a>(
+    vertices: impl Iterator<Item = &'a Vertex>,
+    (width, height): (u32, u32),
+) -> Vec<Rectangle<u32>> {
+    let mut seen = HashSet::new();
+    vertices
+        .filter_map(|vertex| {
+            let [left, top, right, bottom] = vertex.tex_rect();
+            let rect = Rectangle {
+                min: [
+                    (left * width as f32).floor() as u32,
+                    (top * height as f32).floor() as u32,
+                ],
+                max: [
+                    (right * width as f32).ceil() as u32,
+                    (bottom * height as f32).ceil() as u32,
+                ],
+            };
+            seen.insert((rect.min, rect.max)).then_some(rect)
+        })
+        .collect()
+}
+
+/// Copies the single channel `texture` into CPU memory, blocking until the copy
+/// finished.
+pub(crate) fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<Vec<u8>, BrushError> {
+    let size = texture.size();
+    // Rows of texture to buffer copies have to be aligned.
+    let padded_row = size
+        .width
+        .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("wgpu-text Atlas Readback Buffer"),
+        size: padded_row as u64 * size.height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("wgpu-text Atlas Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+    queue.submit([encoder.finish()]);
+
+    let failed =
+        |error: &dyn std::fmt::Display| BrushError::AtlasReadback(error.to_string());
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        // The receiver outlives the callback, which is invoked while polling.
+        let _ = sender.send(result);
+    });
+    device
+        .poll(wgpu::PollType::wait_indefinitely())
+        .map_err(|error| failed(&error))?;
+    receiver
+        .recv()
+        .map_err(|error| failed(&error))?
+        .map_err(|error| failed(&error))?;
+
+    let view = slice.get_mapped_range().map_err(|error| failed(&error))?;
+    Ok(view
+        .chunks_exact(padded_row as usize)
+        .flat_map(|row| &row[..size.width as usize])
+        .copied()
+        .collect())
+}
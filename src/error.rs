@@ -1,10 +1,27 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, io};
+
+use glyph_brush::FontId;
 
 /// Result of `TextBrush` errors and problems.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BrushError {
     /// Cache texture exceeded the limitations stated in `wgpu::Device`.
+    ///
+    /// Since the glyphs are spilled into multiple [pages](crate::GlyphAtlas#pages),
+    /// this only happens if a single section doesn't fit a texture of the maximum size,
+    /// or once when a small texture of an unshared atlas would have to exceed it.
     TooBigCacheTexture(u32),
+    /// User provided shader failed parsing or validation.
+    /// Contains the formatted error message.
+    InvalidShader(String),
+    /// The provided font id doesn't refer to a font of the brush.
+    UnknownFont(FontId),
+    /// Reading a glyph cache texture back into CPU memory failed.
+    /// Contains the formatted error message.
+    AtlasReadback(String),
+    /// The immediate size exceeds the `max_immediate_size` limit of the device,
+    /// which is `0` without `wgpu::Features::IMMEDIATES`. Contains the limit.
+    UnsupportedImmediateSize(u32),
 }
 
 impl Error for BrushError {}
@@ -23,6 +40,84 @@ impl Display for BrushError {
                 BrushBuilder::initial_cache_size() and providing bigger cache \
                 texture dimensions."
             ),
+            BrushError::InvalidShader(message) => {
+                write!(f, "The provided shader is invalid:\n{message}")
+            }
+            BrushError::UnknownFont(FontId(id)) => {
+                write!(f, "The font id {id} doesn't refer to a font of the brush.")
+            }
+            BrushError::AtlasReadback(message) => {
+                write!(f, "Reading back the glyph cache texture failed: {message}")
+            }
+            BrushError::UnsupportedImmediateSize(limit) => write!(
+                f,
+                "The immediate size exceeds the immediate size of {limit} bytes \
+                supported by the device. Immediate data requires \
+                'wgpu::Features::IMMEDIATES' and a big enough \
+                'wgpu::Limits {{ max_immediate_size }}'."
+            ),
+        }
+    }
+}
+
+/// Errors of loading a glyph atlas file, see
+/// [`TextBrush::load_atlas()`](crate::TextBrush::load_atlas).
+#[derive(Debug)]
+pub enum AtlasFileError {
+    /// Reading the file failed, or it ended unexpectedly.
+    Io(io::Error),
+    /// The data isn't a glyph atlas file or is corrupted.
+    InvalidFormat,
+    /// The file was written by an incompatible version of the format.
+    UnsupportedVersion(u32),
+    /// The file was written for different fonts, so its glyphs are stale.
+    FontMismatch,
+    /// Caching the restored glyphs failed.
+    Brush(BrushError),
+}
+
+impl Error for AtlasFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AtlasFileError::Io(error) => Some(error),
+            AtlasFileError::Brush(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AtlasFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasFileError::Io(error) => {
+                write!(f, "wgpu-text: Reading the atlas file failed: {error}")
+            }
+            AtlasFileError::InvalidFormat => {
+                write!(f, "wgpu-text: The atlas file is invalid.")
+            }
+            AtlasFileError::UnsupportedVersion(version) => write!(
+                f,
+                "wgpu-text: The atlas file version {version} isn't supported."
+            ),
+            AtlasFileError::FontMismatch => {
+                write!(
+                    f,
+                    "wgpu-text: The atlas file was written for different fonts."
+                )
+            }
+            AtlasFileError::Brush(error) => error.fmt(f),
         }
     }
 }
+
+impl From<io::Error> for AtlasFileError {
+    fn from(error: io::Error) -> Self {
+        AtlasFileError::Io(error)
+    }
+}
+
+impl From<BrushError> for AtlasFileError {
+    fn from(error: BrushError) -> Self {
+        AtlasFileError::Brush(error)
+    }
+}
@@ -0,0 +1,50 @@
+use glyph_brush::{Color, ab_glyph::Rect};
+
+/// Information about a single glyph passed to the hook of
+/// [`TextBrush::transform_glyphs()`](crate::TextBrush::transform_glyphs).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct GlyphInfo {
+    /// Index of the section in the order it was queued.
+    pub section: usize,
+    /// Index of the glyph in its section.
+    ///
+    /// Only visible glyphs are counted, meaning whitespace characters and glyphs
+    /// outside of the section bounds are skipped.
+    pub index: usize,
+    /// Pixel coordinates of the glyph quad, before applying any transformation.
+    pub rect: Rect,
+    /// Text color of the glyph.
+    pub color: Color,
+}
+
+/// Per-glyph transformation applied on top of the section layout.
+///
+/// Scaling and rotation are done around the center of the glyph quad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphTransform {
+    /// Offset in pixels.
+    pub offset: (f32, f32),
+    /// Scale factor.
+    pub scale: f32,
+    /// Clockwise rotation in radians.
+    pub rotation: f32,
+    /// Color of the glyph.
+    pub color: Color,
+}
+
+impl GlyphTransform {
+    /// Transformation which leaves the glyph with the provided `color` unchanged.
+    pub fn identity(color: Color) -> Self {
+        Self {
+            offset: (0.0, 0.0),
+            scale: 1.0,
+            rotation: 0.0,
+            color,
+        }
+    }
+
+    pub(crate) fn to_array(self) -> [f32; 4] {
+        [self.offset.0, self.offset.1, self.scale, self.rotation]
+    }
+}
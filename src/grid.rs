@@ -0,0 +1,96 @@
+use glyph_brush::{
+    BuiltInLineBreaker, GlyphChange, GlyphPositioner, Layout, SectionGeometry,
+    SectionGlyph, ToSectionText,
+    ab_glyph::{Font, Rect, point},
+};
+
+/// Positioning of the glyphs relative to the device pixel grid before they are
+/// rasterized, see [`BrushBuilder::with_glyph_positioning()`](crate::BrushBuilder::with_glyph_positioning).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GlyphPositioning {
+    /// Glyphs keep the subpixel positions of the layout. Glyphs at subpixel offsets
+    /// differing by more than the position tolerance of the cache
+    /// ([`BrushBuilder::draw_cache_position_tolerance()`](crate::BrushBuilder::draw_cache_position_tolerance))
+    /// are rasterized and cached separately.
+    #[default]
+    Subpixel,
+    /// Baselines snap to the pixel grid, so text doesn't get blurred vertically,
+    /// while the glyphs keep their horizontal subpixel positions.
+    SnapBaseline,
+    /// Baselines and glyph origins snap to the pixel grid. Each glyph is cached only
+    /// once per scale, at the cost of less even glyph spacing.
+    PixelAligned,
+}
+
+/// Alignment of the glyphs of a section to the device pixel grid. Sections are
+/// laid out in device pixels, see
+/// [`BrushBuilder::with_scale_factor()`](crate::BrushBuilder::with_scale_factor).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct PixelGrid {
+    pub positioning: GlyphPositioning,
+    /// Whether the glyph quads snap to the grid.
+    pub snap_quads: bool,
+}
+
+/// Built-in layout which snaps the glyph positions to the pixel grid.
+#[derive(Debug, Clone, Copy, Hash)]
+pub(crate) struct GridLayout {
+    pub layout: Layout<BuiltInLineBreaker>,
+    pub grid: PixelGrid,
+}
+
+impl GlyphPositioner for GridLayout {
+    fn calculate_glyphs<F, S>(
+        &self,
+        fonts: &[F],
+        geometry: &SectionGeometry,
+        sections: &[S],
+    ) -> Vec<SectionGlyph>
+    where
+        F: Font,
+        S: ToSectionText,
+    {
+        let mut glyphs = self.layout.calculate_glyphs(fonts, geometry, sections);
+        match self.grid.positioning {
+            GlyphPositioning::Subpixel => {}
+            GlyphPositioning::SnapBaseline => {
+                for glyph in &mut glyphs {
+                    glyph.glyph.position.y = glyph.glyph.position.y.round();
+                }
+            }
+            GlyphPositioning::PixelAligned => {
+                for glyph in &mut glyphs {
+                    let position = glyph.glyph.position;
+                    glyph.glyph.position = point(position.x.round(), position.y.round());
+                }
+            }
+        }
+        glyphs
+    }
+
+    fn bounds_rect(&self, geometry: &SectionGeometry) -> Rect {
+        self.layout.bounds_rect(geometry)
+    }
+
+    fn recalculate_glyphs<F, S, P>(
+        &self,
+        previous: P,
+        change: GlyphChange,
+        fonts: &[F],
+        geometry: &SectionGeometry,
+        sections: &[S],
+    ) -> Vec<SectionGlyph>
+    where
+        F: Font,
+        S: ToSectionText,
+        P: IntoIterator<Item = SectionGlyph>,
+    {
+        match self.grid.positioning {
+            // Moving snapped glyphs by the geometry change would leave the grid.
+            GlyphPositioning::Subpixel => self
+                .layout
+                .recalculate_glyphs(previous, change, fonts, geometry, sections),
+            _ => self.calculate_glyphs(fonts, geometry, sections),
+        }
+    }
+}
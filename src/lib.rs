@@ -13,17 +13,132 @@
 //! To learn about GPU texture caching, see
 //! [`caching behaviour`](https://docs.rs/glyph_brush/latest/glyph_brush/struct.GlyphBrush.html#caching-behaviour)
 //!
+//! # Color pipeline
+//!
+//! Text colors ([`glyph_brush::Extra::color`]) are treated as **linear** RGBA values.
+//! The fragment shader multiplies the RGB components by the brush luminance
+//! ([`BrushBuilder::with_luminance()`]) and the section luminance
+//! ([`SectionStyle::with_luminance()`]), and the alpha component by the glyph
+//! coverage, after which the result is alpha blended onto the render target:
+//!
+//! - **`*Srgb` formats** (e.g. `Bgra8UnormSrgb`) - the hardware encodes the linear output
+//!   to sRGB, so `[1.0, 1.0, 1.0, 1.0]` is the brightest displayable white.
+//! - **unorm formats** (e.g. `Rgba8Unorm`, `Rgb10a2Unorm`) - the output is written as is
+//!   and clamped to `[0.0, 1.0]`, so any luminance above `1.0` saturates.
+//! - **float formats** (e.g. `Rgba16Float`) - the output isn't clamped. Values above `1.0`
+//!   are brighter than SDR white, so use the luminance multipliers (or color components
+//!   bigger than `1.0`) to match the brightness of your HDR scene.
+//!
+//! # Custom shaders
+//!
+//! The built-in shader ([`DEFAULT_SHADER`]) can be customized in two ways:
+//!
+//! - [`BrushBuilder::with_fragment_shader()`] injects a user function into the
+//!   built-in shader. It's called for every fragment with the interpolated vertex
+//!   output and the glyph coverage sampled from the cache texture:
+//!   ```wgsl
+//!   fn text_fragment(in: VertexOutput, coverage: f32) -> vec4<f32> {
+//!       let color = in.color * section_fill(in.position);
+//!       return vec4<f32>(color.rgb * uniforms.luminance * style.luminance, color.a * coverage);
+//!   }
+//!   ```
+//! - [`BrushBuilder::with_shader()`] replaces the whole shader module. It must contain
+//!   a `vs_main` vertex and a `fs_main` fragment entry point.
+//!
+//! Both have access to the following interface:
+//! ```wgsl
+//! // Per glyph (instance) vertex input.
+//! struct VertexInput {
+//!     @builtin(vertex_index) vertex_index: u32,
+//!     @location(0) top_left: vec3<f32>,
+//!     @location(1) bottom_right: vec2<f32>,
+//!     @location(2) tex_top_left: vec2<f32>,
+//!     @location(3) tex_bottom_right: vec2<f32>,
+//!     @location(4) color: vec4<f32>,
+//!     // Glyph offset (xy), scale (z) and rotation (w), see `TextBrush::transform_glyphs()`.
+//!     @location(5) transform: vec4<f32>,
+//! }
+//!
+//! struct VertexOutput {
+//!     @builtin(position) clip_position: vec4<f32>,
+//!     @location(0) tex_pos: vec2<f32>,
+//!     @location(1) color: vec4<f32>,
+//!     // Position in the section layout space (before applying the matrix).
+//!     @location(2) position: vec2<f32>,
+//! }
+//!
+//! struct Uniforms {
+//!     matrix: mat4x4<f32>,
+//!     luminance: f32,
+//!     // Render target dimensions in pixels.
+//!     viewport: vec2<f32>,
+//!     // World space camera axes used by world sized billboards.
+//!     billboard_right: vec4<f32>,
+//!     billboard_up: vec4<f32>,
+//! }
+//!
+//! // Section style, bound with a dynamic offset for each styled section.
+//! struct Style {
+//!     // Section transformation, applied before `Uniforms::matrix`.
+//!     transform: mat4x4<f32>,
+//!     fill_colors: array<vec4<f32>, 2>,
+//!     fill_points: vec4<f32>,
+//!     // Billboard anchor (xyz) and world scale (w).
+//!     billboard: vec4<f32>,
+//!     fill_kind: u32,
+//!     // 0 - screen or world, 1 - screen sized billboard, 2 - world sized billboard
+//!     placement: u32,
+//!     luminance: f32,
+//! }
+//!
+//! @group(0) @binding(0) var<uniform> uniforms: Uniforms;
+//! @group(0) @binding(1) var texture: texture_2d<f32>;
+//! @group(0) @binding(2) var tex_sampler: sampler;
+//! @group(0) @binding(3) var<uniform> style: Style;
+//!
+//! // Returns the section fill (gradient) color at the provided layout position.
+//! fn section_fill(position: vec2<f32>) -> vec4<f32>;
+//! ```
+//! Glyph quads are drawn as triangle strips of 4 vertices per instance.
+//!
+//! Per-frame data like time, mouse position or palettes can be provided by registering
+//! additional bind group layouts ([`BrushBuilder::with_bind_group_layout()`], groups
+//! `1, 2, 3...`) or immediate data ([`BrushBuilder::with_immediate_size()`]) and
+//! binding the matching data with [`TextBrush::draw_with()`].
+//!
+//! Custom shaders are checked against the device and the brush while building, use
+//! [`BrushBuilder::try_build()`] to handle shaders which don't fit instead of panicking.
+//!
 //! > Look through [`examples`](https://github.com/Blatko1/wgpu_text/tree/master/examples).
 
 // TODO fix VULKAN error when running examples
+mod atlas;
 mod brush;
 mod cache;
+mod debug;
 mod error;
+mod glyph;
+mod grid;
+mod persist;
 mod pipeline;
+mod shader;
+mod stats;
+mod style;
+mod upload;
 
-pub use brush::{BrushBuilder, TextBrush};
-pub use error::BrushError;
+pub use atlas::{EvictionPolicy, GlyphAtlas};
+pub use brush::{BrushBuilder, DrawOptions, RenderTarget, ShrinkPolicy, TextBrush};
+pub use debug::{AtlasImage, DebugOverlay};
+pub use error::{AtlasFileError, BrushError};
+pub use glyph::{GlyphInfo, GlyphTransform};
 pub use glyph_brush;
+pub use grid::GlyphPositioning;
+pub use shader::DEFAULT_SHADER;
+pub use stats::BrushStats;
+pub use style::{
+    BillboardSize, Fill, Placement, SectionStyle, SectionTransform, StyledSection,
+};
+pub use upload::UploadMode;
 
 /// Represents a two-dimensional array matrix with 4x4 dimensions.
 pub type Matrix = [[f32; 4]; 4];
@@ -0,0 +1,253 @@
+use std::io::{self, Read, Write};
+
+use glyph_brush::{
+    FontId, SectionGlyph,
+    ab_glyph::{GlyphId, PxScale, point},
+};
+
+use crate::error::AtlasFileError;
+
+/// Identifies glyph atlas files.
+const MAGIC: [u8; 8] = *b"WGTATLAS";
+/// Version of the glyph atlas file format, incremented on every change.
+const VERSION: u32 = 1;
+
+/// Glyph of an atlas file together with its bitmap.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StoredGlyph {
+    /// The glyph, positioned at its subpixel offset.
+    pub glyph: SectionGlyph,
+    /// Dimensions of the bitmap, including the padding of one texel on every side
+    /// the glyph has in the cache texture.
+    pub width: u32,
+    pub height: u32,
+    /// Glyph coverage of every texel of the bitmap, row by row.
+    pub texels: Vec<u8>,
+}
+
+/// Writes an atlas file with the `glyphs` for the fonts with the provided hashes.
+///
+/// Layout, little endian: magic, version, font count, a `u64` hash per font, glyph
+/// count, and per glyph its font index (`u32`), glyph id (`u16`), scale and
+/// subpixel position (`f32` each), bitmap width and height (`u32` each) and the
+/// bitmap texels.
+pub(crate) fn write_glyphs(
+    mut writer: impl Write,
+    font_hashes: &[u64],
+    glyphs: &[StoredGlyph],
+) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(font_hashes.len() as u32).to_le_bytes())?;
+    for hash in font_hashes {
+        writer.write_all(&hash.to_le_bytes())?;
+    }
+
+    writer.write_all(&(glyphs.len() as u32).to_le_bytes())?;
+    for StoredGlyph {
+        glyph: SectionGlyph { glyph, font_id, .. },
+        width,
+        height,
+        texels,
+    } in glyphs
+    {
+        writer.write_all(&(font_id.0 as u32).to_le_bytes())?;
+        writer.write_all(&glyph.id.0.to_le_bytes())?;
+        for value in [
+            glyph.scale.x,
+            glyph.scale.y,
+            glyph.position.x,
+            glyph.position.y,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        writer.write_all(texels)?;
+    }
+    writer.flush()
+}
+
+/// Reads the glyphs of an atlas file, rejecting files written for fonts other than
+/// the ones with the provided hashes.
+pub(crate) fn read_glyphs(
+    mut reader: impl Read,
+    font_hashes: &[u64],
+) -> Result<Vec<StoredGlyph>, AtlasFileError> {
+    if read_array(&mut reader)? != MAGIC {
+        return Err(AtlasFileError::InvalidFormat);
+    }
+    let version = u32::from_le_bytes(read_array(&mut reader)?);
+    if version != VERSION {
+        return Err(AtlasFileError::UnsupportedVersion(version));
+    }
+
+    let fonts = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+    if fonts != font_hashes.len() {
+        return Err(AtlasFileError::FontMismatch);
+    }
+    for hash in font_hashes {
+        if u64::from_le_bytes(read_array(&mut reader)?) != *hash {
+            return Err(AtlasFileError::FontMismatch);
+        }
+    }
+
+    let count = u32::from_le_bytes(read_array(&mut reader)?);
+    let mut glyphs = Vec::new();
+    for _ in 0..count {
+        let font = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+        let id = u16::from_le_bytes(read_array(&mut reader)?);
+        let mut values = [0.0; 4];
+        for value in &mut values {
+            *value = f32::from_le_bytes(read_array(&mut reader)?);
+        }
+        let [scale_x, scale_y, x, y] = values;
+        if font >= fonts || values.iter().any(|value| !value.is_finite()) {
+            return Err(AtlasFileError::InvalidFormat);
+        }
+
+        let width = u32::from_le_bytes(read_array(&mut reader)?);
+        let height = u32::from_le_bytes(read_array(&mut reader)?);
+        let len = width as u64 * height as u64;
+        // Read without allocating the size a corrupted file claims up front.
+        let mut texels = Vec::new();
+        (&mut reader).take(len).read_to_end(&mut texels)?;
+        if texels.len() as u64 != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        glyphs.push(StoredGlyph {
+            glyph: SectionGlyph {
+                section_index: 0,
+                byte_index: 0,
+                glyph: GlyphId(id).with_scale_and_position(
+                    PxScale {
+                        x: scale_x,
+                        y: scale_y,
+                    },
+                    point(x, y),
+                ),
+                font_id: FontId(font),
+            },
+            width,
+            height,
+            texels,
+        });
+    }
+    Ok(glyphs)
+}
+
+/// Hashes the font data with 64 bit FNV-1a, which is stable across builds.
+pub(crate) fn font_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASHES: [u64; 2] = [7, 11];
+
+    fn glyph(font: usize, id: u16, width: u32, height: u32) -> StoredGlyph {
+        StoredGlyph {
+            glyph: SectionGlyph {
+                section_index: 0,
+                byte_index: 0,
+                glyph: GlyphId(id)
+                    .with_scale_and_position(PxScale::from(24.0), point(0.25, 0.5)),
+                font_id: FontId(font),
+            },
+            width,
+            height,
+            texels: (0..width * height).map(|texel| texel as u8).collect(),
+        }
+    }
+
+    fn file(glyphs: &[StoredGlyph]) -> Vec<u8> {
+        let mut file = Vec::new();
+        write_glyphs(&mut file, &HASHES, glyphs).unwrap();
+        file
+    }
+
+    #[test]
+    fn round_trip() {
+        let glyphs = [glyph(0, 36, 5, 7), glyph(1, 68, 3, 3), glyph(1, 3, 0, 0)];
+        let read = read_glyphs(&file(&glyphs)[..], &HASHES).unwrap();
+        assert_eq!(read, glyphs);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = file(&[glyph(0, 36, 5, 7)]);
+        data[0] = b'X';
+        assert!(matches!(
+            read_glyphs(&data[..], &HASHES),
+            Err(AtlasFileError::InvalidFormat)
+        ));
+
+        let mut data = file(&[]);
+        data[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(
+            read_glyphs(&data[..], &HASHES),
+            Err(AtlasFileError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_fonts() {
+        let data = file(&[glyph(0, 36, 5, 7)]);
+        for hashes in [&[7][..], &[7, 12], &[11, 7]] {
+            assert!(matches!(
+                read_glyphs(&data[..], hashes),
+                Err(AtlasFileError::FontMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_glyphs() {
+        // Font index past the font count.
+        let data = file(&[glyph(2, 36, 5, 7)]);
+        assert!(matches!(
+            read_glyphs(&data[..], &HASHES),
+            Err(AtlasFileError::InvalidFormat)
+        ));
+
+        let mut data = file(&[glyph(0, 36, 5, 7)]);
+        // Scale of the first glyph, after the header, glyph count, font and id.
+        let scale = 8 + 4 + 4 + 16 + 4 + 4 + 2;
+        data[scale..scale + 4].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert!(matches!(
+            read_glyphs(&data[..], &HASHES),
+            Err(AtlasFileError::InvalidFormat)
+        ));
+
+        // Bitmap bigger than the rest of the file.
+        let mut data = file(&[glyph(0, 36, 5, 7)]);
+        let width = scale + 16;
+        data[width..width + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            read_glyphs(&data[..], &HASHES),
+            Err(AtlasFileError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = file(&[glyph(0, 36, 5, 7), glyph(1, 68, 3, 3)]);
+        for len in [0, 4, 20, data.len() / 2, data.len() - 1] {
+            assert!(matches!(
+                read_glyphs(&data[..len], &HASHES),
+                Err(AtlasFileError::Io(_))
+            ));
+        }
+    }
+}
@@ -1,12 +1,24 @@
-use std::num::NonZeroU32;
-
-use glyph_brush::{
-    Rectangle,
-    ab_glyph::{Rect, point},
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    num::NonZeroU32,
+    ops::Range,
+    sync::{
+        Mutex, OnceLock, PoisonError,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
 };
+
 use wgpu::util::DeviceExt;
 
-use crate::{Matrix, cache::Cache};
+use crate::{
+    DrawOptions, Matrix, RenderTarget, ShrinkPolicy,
+    cache::Cache,
+    debug::{DebugOverlay, DebugPipelines},
+    glyph::{GlyphInfo, GlyphTransform},
+    style::{SectionExtra, StyleUniforms},
+    upload::Uploader,
+};
+use glyph_brush::ab_glyph::{Rect, point};
 
 /// Responsible for drawing text.
 #[derive(Debug)]
@@ -14,113 +26,549 @@ pub struct Pipeline {
     inner: wgpu::RenderPipeline,
     cache: Cache,
 
-    vertex_buffer: wgpu::Buffer,
-    vertex_buffer_len: usize,
+    vertex_buffer: VertexBuffer,
     vertices: u32,
+    /// Consecutive instances sharing the same draw layer, section style and atlas page.
+    style_runs: Vec<StyleRun>,
+    /// Untransformed vertices of the last processed frame, in draw order.
+    glyph_vertices: Vec<Vertex>,
+    /// Placement and draw data of each processed section with glyphs, in draw order.
+    sections: Vec<SectionSlot>,
+    /// Sections queued for the last processed frame, in the queue order.
+    queued: Vec<QueuedSection>,
+    /// End of the last section slot in the vertex buffer.
+    slots_end: usize,
+    /// Whether the vertex buffer contents differ from `glyph_vertices` because of
+    /// glyph transformations.
+    transformed: AtomicBool,
+
+    variants: Variants,
+    /// Recorded render bundles by target and draw layer, cleared whenever the
+    /// recorded commands become outdated.
+    bundles: Mutex<HashMap<(RenderTarget, Option<u32>), RecordedBundle>>,
+    /// Pipelines of the debug overlay, created on first use.
+    debug: OnceLock<DebugPipelines>,
+    /// Instances drawn and draw calls issued since the last processing.
+    instances_drawn: AtomicU64,
+    draw_calls: AtomicU32,
+}
+
+/// Section slots are allocated in multiples of this many vertices, leaving room for
+/// changing text to grow in place.
+const SLOT_ALIGNMENT: usize = 8;
+/// Number of unused vertices between the section slots always tolerated before
+/// compacting them.
+const MIN_COMPACTION_WASTE: usize = 1024;
+
+/// Allocation settings of the vertex buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct VertexAllocation {
+    /// Number of preallocated vertices, also the smallest capacity after shrinking.
+    pub capacity: usize,
+    pub growth: f32,
+    pub shrink: Option<ShrinkPolicy>,
+}
+
+impl Default for VertexAllocation {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            growth: 1.5,
+            shrink: None,
+        }
+    }
+}
+
+/// Vertex (instance) buffer growing and shrinking according to its [`VertexAllocation`].
+#[derive(Debug)]
+struct VertexBuffer {
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    allocation: VertexAllocation,
+    /// Consecutive updates using less than the shrink threshold.
+    underused: u32,
+    reallocations: u64,
+}
+
+/// Render pipelines for targets other than the one the brush was built for,
+/// created lazily.
+#[derive(Debug)]
+struct Variants {
+    device: wgpu::Device,
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState,
+    multiview_mask: Option<NonZeroU32>,
+
+    target: RenderTarget,
+    pipelines: Mutex<HashMap<RenderTarget, wgpu::RenderPipeline>>,
 }
 
 impl Pipeline {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         render_format: wgpu::TextureFormat,
         depth_stencil: Option<wgpu::DepthStencilState>,
         multisample: wgpu::MultisampleState,
         multiview_mask: Option<NonZeroU32>,
-        tex_dimensions: (u32, u32),
+        textures: &[wgpu::Texture],
+        sampler: &wgpu::SamplerDescriptor,
         matrix: Matrix,
+        luminance: f32,
+        viewport: (f32, f32),
+        shader: &str,
+        bind_group_layouts: &[wgpu::BindGroupLayout],
+        immediate_size: u32,
+        vertex_allocation: VertexAllocation,
     ) -> Pipeline {
-        let cache = Cache::new(device, tex_dimensions, matrix);
+        let cache = Cache::new(device, textures, sampler, matrix, luminance, viewport);
 
-        let shader =
-            device.create_shader_module(wgpu::include_wgsl!("shader/shader.wgsl"));
-
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("wgpu-text Vertex Buffer"),
-            size: 0,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("wgpu-text Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader.into()),
         });
 
+        let vertex_buffer = VertexBuffer::new(device, vertex_allocation);
+
         let pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("wgpu-text Render Pipeline Layout"),
-                bind_group_layouts: &[Some(&cache.bind_group_layout)],
-                immediate_size: 0,
+                bind_group_layouts: &std::iter::once(&cache.bind_group_layout)
+                    .chain(bind_group_layouts)
+                    .map(Some)
+                    .collect::<Vec<_>>(),
+                immediate_size,
             });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("wgpu-text Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[Some(Vertex::buffer_layout())],
-                compilation_options: Default::default(),
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                strip_index_format: Some(wgpu::IndexFormat::Uint16),
-                ..Default::default()
+        let variants = Variants {
+            device: device.clone(),
+            shader,
+            layout: pipeline_layout,
+            target: RenderTarget {
+                format: render_format,
+                sample_count: multisample.count,
+                depth_format: depth_stencil.as_ref().map(|state| state.format),
             },
             depth_stencil,
             multisample,
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: render_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            cache: None,
             multiview_mask,
-        });
+            pipelines: Mutex::new(HashMap::new()),
+        };
 
         Self {
-            inner: pipeline,
+            inner: variants.create(variants.target),
             cache,
 
             vertex_buffer,
-            vertex_buffer_len: 0,
             vertices: 0,
+            style_runs: Vec::new(),
+            glyph_vertices: Vec::new(),
+            sections: Vec::new(),
+            queued: Vec::new(),
+            slots_end: 0,
+            transformed: AtomicBool::new(false),
+
+            variants,
+            bundles: Mutex::new(HashMap::new()),
+            debug: OnceLock::new(),
+            instances_drawn: AtomicU64::new(0),
+            draw_calls: AtomicU32::new(0),
+        }
+    }
+
+    /// Creates the render pipeline for the `target` if it doesn't exist yet.
+    pub fn prepare_target(&self, target: RenderTarget) {
+        if target != self.variants.target {
+            self.variants.get(target);
         }
     }
 
     /// Raw draw.
-    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass, options: &DrawOptions) {
+        if self.vertices != 0 {
+            match options.target {
+                Some(target) if target != self.variants.target => {
+                    rpass.set_pipeline(&self.variants.get(target))
+                }
+                _ => rpass.set_pipeline(&self.inner),
+            }
+            rpass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
+            for (index, bind_group) in options.bind_groups.iter().enumerate() {
+                rpass.set_bind_group(index as u32 + 1, *bind_group, &[]);
+            }
+            if !options.immediates.is_empty() {
+                rpass.set_immediates(0, options.immediates);
+            }
+
+            for run in self.runs(options.layer) {
+                rpass.set_bind_group(
+                    0,
+                    &self.cache.bind_groups[run.page as usize],
+                    &[self.cache.style_offset(run.style)],
+                );
+                rpass.draw(0..4, run.instances.clone());
+                self.instances_drawn
+                    .fetch_add(run.instances.len() as u64, Ordering::Relaxed);
+                self.draw_calls.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the instances drawn and the draw calls issued since the last
+    /// [`Pipeline::reset_draw_stats()`].
+    pub fn draw_stats(&self) -> (u64, u32) {
+        (
+            self.instances_drawn.load(Ordering::Relaxed),
+            self.draw_calls.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn reset_draw_stats(&self) {
+        self.instances_drawn.store(0, Ordering::Relaxed);
+        self.draw_calls.store(0, Ordering::Relaxed);
+    }
+
+    /// Draws the debug overlay into the target the brush was built for.
+    pub fn draw_debug(&self, rpass: &mut wgpu::RenderPass, overlay: &DebugOverlay) {
+        let debug = self.debug.get_or_init(|| {
+            DebugPipelines::new(
+                &self.variants.device,
+                &self.cache.bind_group_layout,
+                self.variants.target,
+                self.variants.multisample,
+                self.variants.multiview_mask,
+            )
+        });
+
+        if overlay.atlas {
+            let quads = overlay.atlas_quads(&self.cache.texture_dimensions());
+            let buffer = self.variants.device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("wgpu-text Debug Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&quads),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+            );
+            rpass.set_pipeline(&debug.atlas);
+            rpass.set_vertex_buffer(0, buffer.slice(..));
+            for (page, bind_group) in self.cache.bind_groups.iter().enumerate() {
+                rpass.set_bind_group(0, bind_group, &[self.cache.style_offset(0)]);
+                rpass.draw(0..4, page as u32..page as u32 + 1);
+            }
+        }
+
+        if overlay.outlines && self.vertices != 0 {
+            rpass.set_pipeline(&debug.outlines);
+            rpass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
+            rpass.set_index_buffer(debug.indices.slice(..), wgpu::IndexFormat::Uint16);
+            for run in self.runs(None) {
+                rpass.set_bind_group(
+                    0,
+                    &self.cache.bind_groups[run.page as usize],
+                    &[self.cache.style_offset(run.style)],
+                );
+                rpass.draw_indexed(0..5, 0, run.instances.clone());
+            }
+        }
+    }
+
+    /// Returns the untransformed vertices of the last processed frame.
+    pub fn glyph_vertices(&self) -> &[Vertex] {
+        &self.glyph_vertices
+    }
+
+    /// Returns the untransformed vertices of the last processed frame drawn from
+    /// the atlas `page`.
+    pub fn page_vertices(&self, page: u32) -> impl Iterator<Item = &Vertex> {
+        self.sections
+            .iter()
+            .filter(move |slot| slot.page == page)
+            .flat_map(|slot| &self.glyph_vertices[slot.vertices.clone()])
+    }
+
+    /// Returns the render bundle drawing the same as [`Pipeline::draw()`],
+    /// recording it if the previous one is outdated.
+    pub fn render_bundle(&self, options: &DrawOptions) -> wgpu::RenderBundle {
+        let target = options.target.unwrap_or(self.variants.target);
+        let mut bundles = self.bundles.lock().unwrap_or_else(PoisonError::into_inner);
+        let recorded = bundles.entry((target, options.layer));
+        if let Entry::Occupied(recorded) = &recorded
+            && recorded.get().matches(options)
+        {
+            return recorded.get().bundle.clone();
+        }
+
+        let bundle = self.record_bundle(target, options);
+        recorded.insert_entry(RecordedBundle {
+            bundle: bundle.clone(),
+            bind_groups: options
+                .bind_groups
+                .iter()
+                .map(|&group| group.clone())
+                .collect(),
+            immediates: options.immediates.to_vec(),
+        });
+        bundle
+    }
+
+    fn record_bundle(
+        &self,
+        target: RenderTarget,
+        options: &DrawOptions,
+    ) -> wgpu::RenderBundle {
+        let pipeline = if target == self.variants.target {
+            self.inner.clone()
+        } else {
+            self.variants.get(target)
+        };
+        let depth_stencil = self.variants.depth_stencil(target).map(|state| {
+            wgpu::RenderBundleDepthStencil {
+                format: state.format,
+                depth_read_only: state.is_depth_read_only(),
+                stencil_read_only: state.is_stencil_read_only(None),
+            }
+        });
+
+        let mut encoder = self.variants.device.create_render_bundle_encoder(
+            &wgpu::RenderBundleEncoderDescriptor {
+                label: Some("wgpu-text Render Bundle Encoder"),
+                color_formats: &[Some(target.format)],
+                depth_stencil,
+                sample_count: target.sample_count,
+                multiview: self.variants.multiview_mask,
+            },
+        );
         if self.vertices != 0 {
-            rpass.set_pipeline(&self.inner);
-            rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            rpass.set_bind_group(0, &self.cache.bind_group, &[]);
+            encoder.set_pipeline(&pipeline);
+            encoder.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
+            for (index, bind_group) in options.bind_groups.iter().enumerate() {
+                encoder.set_bind_group(index as u32 + 1, *bind_group, &[]);
+            }
+            if !options.immediates.is_empty() {
+                encoder.set_immediates(0, options.immediates);
+            }
 
-            rpass.draw(0..4, 0..self.vertices);
+            for run in self.runs(options.layer) {
+                encoder.set_bind_group(
+                    0,
+                    &self.cache.bind_groups[run.page as usize],
+                    &[self.cache.style_offset(run.style)],
+                );
+                encoder.draw(0..4, run.instances.clone());
+            }
         }
+        encoder.finish(&wgpu::RenderBundleDescriptor {
+            label: Some("wgpu-text Render Bundle"),
+        })
+    }
+
+    /// Drops the recorded render bundles.
+    fn invalidate_bundles(&mut self) {
+        self.bundles
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Style runs of the provided layer, or all of them.
+    fn runs(&self, layer: Option<u32>) -> impl Iterator<Item = &StyleRun> {
+        self.style_runs
+            .iter()
+            .filter(move |run| layer.is_none_or(|layer| layer == run.layer))
     }
-    // TODO look into preallocating the vertex buffer instead of constantly reallocating
+
+    /// Uploads the processed glyph `quads` of the `queued` sections, writing only
+    /// the sections which changed since the last frame.
+    ///
+    /// Every section occupies its own slot of the vertex buffer. Unchanged sections
+    /// keep their slots (even if other sections were added or removed before them),
+    /// changed ones are rewritten in place if they fit or moved to the end of the
+    /// buffer. The slots are compacted once too much space is wasted.
     pub fn update_vertex_buffer(
         &mut self,
-        vertices: Vec<Vertex>,
+        quads: Vec<GlyphQuad>,
+        queued: Vec<QueuedSection>,
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        uploader: &mut Uploader,
     ) {
-        self.vertices = vertices.len() as u32;
-        let data: &[u8] = bytemuck::cast_slice(&vertices);
+        self.invalidate_bundles();
 
-        if vertices.len() > self.vertex_buffer_len {
-            self.vertex_buffer_len = vertices.len();
+        // Groups the layers into contiguous ranges while keeping the queue order
+        // within each layer.
+        let ranges = section_ranges(&quads, &queued);
+        let mut order: Vec<usize> = (0..queued.len())
+            .filter(|&index| !ranges[index].is_empty())
+            .collect();
+        order.sort_by_key(|&index| queued[index].layer);
 
-            self.vertex_buffer =
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("wgpu-text Vertex Buffer"),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                    contents: data,
-                });
+        let mut vertices = Vec::with_capacity(quads.len());
+        let mut slots: Vec<SectionSlot> = order
+            .into_iter()
+            .map(|index| {
+                let range = ranges[index].clone();
+                let start = vertices.len();
+                vertices.extend(quads[range.clone()].iter().map(|quad| quad.vertex));
+                SectionSlot {
+                    vertices: start..vertices.len(),
+                    offset: 0,
+                    capacity: 0,
+                    section: index as u32,
+                    key: queued[index].key,
+                    style: queued[index].style,
+                    layer: queued[index].layer,
+                    page: quads[range.start].page,
+                }
+            })
+            .collect();
 
+        let (mut dirty, end) = place_sections(
+            &self.sections,
+            &self.glyph_vertices,
+            self.slots_end,
+            &vertices,
+            &mut slots,
+        );
+        let used = vertices.len();
+        let mut compact = self.transformed.swap(false, Ordering::Relaxed)
+            || wastes_slots(end, used, self.vertex_buffer.capacity);
+        // Reallocating discards the contents of the buffer.
+        if self
+            .vertex_buffer
+            .reserve(if compact { used } else { end }, device)
+        {
+            compact = true;
+        }
+
+        if compact {
+            log::debug!("Compacting {} sections of the vertex buffer", slots.len());
+            for slot in &mut slots {
+                slot.offset = slot.vertices.start;
+                slot.capacity = slot.vertices.len();
+            }
+            dirty = Vec::new();
+            self.vertex_buffer.write(0, &vertices, uploader);
+            self.slots_end = used;
+        } else {
+            self.slots_end = end;
+        }
+        for slot in dirty.into_iter().map(|index| &slots[index]) {
+            self.vertex_buffer.write(
+                slot.offset,
+                &vertices[slot.vertices.clone()],
+                uploader,
+            );
+        }
+
+        self.vertices = used as u32;
+        self.style_runs.clear();
+        for slot in &slots {
+            let instances =
+                slot.offset as u32..(slot.offset + slot.vertices.len()) as u32;
+            match self.style_runs.last_mut() {
+                Some(run)
+                    if run.layer == slot.layer
+                        && run.style == slot.style
+                        && run.page == slot.page
+                        && run.instances.end == instances.start =>
+                {
+                    run.instances.end = instances.end
+                }
+                _ => self.style_runs.push(StyleRun {
+                    layer: slot.layer,
+                    style: slot.style,
+                    page: slot.page,
+                    instances,
+                }),
+            }
+        }
+        self.sections = slots;
+        self.queued = queued;
+        self.glyph_vertices = vertices;
+    }
+
+    /// Updates the styles and layers of the sections of the last processed frame,
+    /// whose glyphs didn't change.
+    pub fn update_sections(
+        &mut self,
+        queued: Vec<QueuedSection>,
+        device: &wgpu::Device,
+        uploader: &mut Uploader,
+    ) {
+        if queued == self.queued {
             return;
         }
-        queue.write_buffer(&self.vertex_buffer, 0, data);
+
+        let mut slots: Vec<&SectionSlot> = self.sections.iter().collect();
+        slots.sort_unstable_by_key(|slot| slot.section);
+        let quads = slots
+            .into_iter()
+            .flat_map(|slot| {
+                self.glyph_vertices[slot.vertices.clone()]
+                    .iter()
+                    .map(|&vertex| GlyphQuad {
+                        vertex,
+                        section: slot.key,
+                        page: slot.page,
+                    })
+            })
+            .collect();
+        self.update_vertex_buffer(quads, queued, device, uploader);
+    }
+
+    /// Uploads the vertices of the last processed frame with per-glyph
+    /// transformations provided by the `hook`.
+    /// Writes the vertices of every section slot transformed by the `hook`. Holes
+    /// between the slots are never drawn, so they're skipped.
+    pub fn transform_glyphs<G>(&self, uploader: &mut Uploader, mut hook: G)
+    where
+        G: FnMut(&GlyphInfo, &mut GlyphTransform),
+    {
+        if self.glyph_vertices.is_empty() {
+            return;
+        }
+
+        let mut vertices = Vec::new();
+        for slot in &self.sections {
+            vertices.clear();
+            let section = &self.glyph_vertices[slot.vertices.clone()];
+            vertices.extend(section.iter().enumerate().map(|(index, vertex)| {
+                let info = GlyphInfo {
+                    section: slot.section as usize,
+                    index,
+                    rect: Rect {
+                        min: point(vertex.top_left[0], vertex.top_left[1]),
+                        max: point(vertex.bottom_right[0], vertex.bottom_right[1]),
+                    },
+                    color: vertex.color,
+                };
+
+                let mut transform = GlyphTransform::identity(vertex.color);
+                hook(&info, &mut transform);
+                Vertex {
+                    color: transform.color,
+                    transform: transform.to_array(),
+                    ..*vertex
+                }
+            }));
+            uploader.write_buffer(
+                &self.vertex_buffer.buffer,
+                (slot.offset * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&vertices),
+            );
+        }
+        self.transformed.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn vertex_capacity(&self) -> usize {
+        self.vertex_buffer.capacity
+    }
+
+    #[inline]
+    pub fn vertex_reallocations(&self) -> u64 {
+        self.vertex_buffer.reallocations
     }
 
     #[inline]
@@ -129,16 +577,371 @@ impl Pipeline {
     }
 
     #[inline]
-    pub fn update_texture(&self, size: Rectangle<u32>, data: &[u8], queue: &wgpu::Queue) {
-        self.cache.update_texture(size, data, queue);
+    pub fn update_styles(
+        &mut self,
+        styles: &[StyleUniforms],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        if self.cache.update_styles(styles, device, queue) {
+            self.invalidate_bundles();
+        }
+    }
+
+    #[inline]
+    pub fn update_viewport(&self, viewport: (f32, f32), queue: &wgpu::Queue) {
+        self.cache.update_viewport(viewport, queue);
+    }
+
+    #[inline]
+    pub fn update_billboard_axes(
+        &self,
+        right: [f32; 3],
+        up: [f32; 3],
+        queue: &wgpu::Queue,
+    ) {
+        self.cache.update_billboard_axes(right, up, queue);
+    }
+
+    #[inline]
+    pub fn update_luminance(&self, luminance: f32, queue: &wgpu::Queue) {
+        self.cache.update_luminance(luminance, queue);
     }
 
     #[inline]
-    pub fn resize_texture(&mut self, device: &wgpu::Device, tex_dimensions: (u32, u32)) {
-        self.cache.recreate_texture(device, tex_dimensions);
+    pub fn set_textures(&mut self, device: &wgpu::Device, textures: &[wgpu::Texture]) {
+        self.cache.set_textures(device, textures);
+        self.invalidate_bundles();
+    }
+}
+
+impl VertexBuffer {
+    fn new(device: &wgpu::Device, allocation: VertexAllocation) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, allocation.capacity),
+            capacity: allocation.capacity,
+            allocation,
+            underused: 0,
+            reallocations: 0,
+        }
+    }
+
+    /// Makes sure the buffer can hold `len` vertices, shrinking it if it stayed
+    /// underused for too long.
+    ///
+    /// Returns `true` if the buffer was reallocated, losing its contents.
+    fn reserve(&mut self, len: usize, device: &wgpu::Device) -> bool {
+        let grown = ((len as f32 * self.allocation.growth).ceil() as usize)
+            .max(len)
+            .max(self.allocation.capacity);
+
+        if len > self.capacity {
+            self.reallocate(device, grown);
+            return true;
+        }
+        if let Some(shrink) = self.allocation.shrink {
+            if (len as f32) < self.capacity as f32 * shrink.max_usage {
+                self.underused += 1;
+                if self.underused >= shrink.frames && grown < self.capacity {
+                    self.reallocate(device, grown);
+                    return true;
+                }
+            } else {
+                self.underused = 0;
+            }
+        }
+        false
+    }
+
+    fn write(&self, offset: usize, vertices: &[Vertex], uploader: &mut Uploader) {
+        uploader.write_buffer(
+            &self.buffer,
+            (offset * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(vertices),
+        );
+    }
+
+    fn reallocate(&mut self, device: &wgpu::Device, capacity: usize) {
+        log::debug!(
+            "Reallocating the vertex buffer: {} -> {capacity} vertices",
+            self.capacity
+        );
+        self.buffer = Self::create_buffer(device, capacity);
+        self.capacity = capacity;
+        self.underused = 0;
+        self.reallocations += 1;
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("wgpu-text Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+impl Variants {
+    fn depth_stencil(&self, target: RenderTarget) -> Option<wgpu::DepthStencilState> {
+        // Depth testing settings of the brush are kept, otherwise the text is drawn
+        // on top without writing any depth.
+        target.depth_format.map(|format| {
+            self.depth_stencil
+                .clone()
+                .map(|state| wgpu::DepthStencilState { format, ..state })
+                .unwrap_or(wgpu::DepthStencilState {
+                    format,
+                    depth_write_enabled: Some(false),
+                    depth_compare: Some(wgpu::CompareFunction::Always),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                })
+        })
+    }
+
+    /// Returns the render pipeline for the `target`, creating it if necessary.
+    fn get(&self, target: RenderTarget) -> wgpu::RenderPipeline {
+        self.pipelines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(target)
+            .or_insert_with(|| self.create(target))
+            .clone()
+    }
+
+    fn create(&self, target: RenderTarget) -> wgpu::RenderPipeline {
+        let depth_stencil = self.depth_stencil(target);
+
+        self.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("wgpu-text Render Pipeline"),
+                layout: Some(&self.layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[Some(Vertex::buffer_layout())],
+                    compilation_options: Default::default(),
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    strip_index_format: Some(wgpu::IndexFormat::Uint16),
+                    ..Default::default()
+                },
+                depth_stencil,
+                multisample: wgpu::MultisampleState {
+                    count: target.sample_count,
+                    ..self.multisample
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: target.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                cache: None,
+                multiview_mask: self.multiview_mask,
+            })
+    }
+}
+
+/// Vertex buffer slot of a processed section.
+#[derive(Debug)]
+struct SectionSlot {
+    /// Vertices of the section within the processed vertices.
+    vertices: Range<usize>,
+    /// First vertex of the slot in the vertex buffer.
+    offset: usize,
+    capacity: usize,
+    /// Index of the section in the order it was queued.
+    section: u32,
+    key: u64,
+    style: u32,
+    layer: u32,
+    /// Glyph atlas page the glyphs of the section are cached in.
+    page: u32,
+}
+
+/// Draw data of a queued section which isn't part of its glyph quads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedSection {
+    /// Identity of the section, see [`SectionExtra::section`].
+    pub key: u64,
+    /// Index of the section style.
+    pub style: u32,
+    /// Draw layer of the section.
+    pub layer: u32,
+}
+
+/// Glyph quad processed by the inner `GlyphBrush`, together with the data which
+/// only stays on the CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct GlyphQuad {
+    pub vertex: Vertex,
+    /// Identity of the section of the glyph, see [`SectionExtra::section`].
+    pub section: u64,
+    /// Glyph atlas page the glyph is cached in.
+    pub page: u32,
+}
+
+impl GlyphQuad {
+    pub fn new(glyph: glyph_brush::GlyphVertex<SectionExtra>) -> GlyphQuad {
+        GlyphQuad {
+            section: glyph.extra.section,
+            page: 0,
+            vertex: Vertex::to_vertex(glyph),
+        }
+    }
+}
+
+/// Splits the `quads` into the glyphs of each of the `queued` sections.
+///
+/// The quads are in the queue order, so each section takes the following quads
+/// with its key. Identical sections queued right after each other have the same
+/// key and share their quads evenly.
+fn section_ranges(quads: &[GlyphQuad], queued: &[QueuedSection]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::with_capacity(queued.len());
+    let mut start = 0;
+    for twins in queued.chunk_by(|a, b| a.key == b.key) {
+        let len = quads[start..]
+            .iter()
+            .take_while(|quad| quad.section == twins[0].key)
+            .count();
+        let each = len / twins.len();
+        for index in 0..twins.len() {
+            let end = if index + 1 == twins.len() {
+                start + len - each * index
+            } else {
+                start + each
+            };
+            ranges.push(start..end);
+            start = end;
+        }
+    }
+    ranges
+}
+
+/// Assigns a vertex buffer slot to each section of the new `vertices`, reusing the
+/// `old` slots of the last frame with its `old_vertices` and end of the slots.
+///
+/// Returns the indices of the slots which have to be written and the new end of the
+/// slots.
+fn place_sections(
+    old: &[SectionSlot],
+    old_vertices: &[Vertex],
+    old_end: usize,
+    vertices: &[Vertex],
+    slots: &mut [SectionSlot],
+) -> (Vec<usize>, usize) {
+    fn bytes(vertices: &[Vertex]) -> &[u8] {
+        bytemuck::cast_slice(vertices)
+    }
+    let mut claimed = vec![false; old.len()];
+    let mut placed: Vec<Option<&SectionSlot>> = vec![None; slots.len()];
+
+    // Sections which stayed the same.
+    for (index, slot) in slots.iter().enumerate() {
+        if let Some(old_slot) = old.get(index)
+            && bytes(&old_vertices[old_slot.vertices.clone()])
+                == bytes(&vertices[slot.vertices.clone()])
+        {
+            claimed[index] = true;
+            placed[index] = Some(old_slot);
+        }
+    }
+    // Unchanged sections moved by sections added or removed before them.
+    if placed.iter().any(Option::is_none) {
+        let mut unclaimed: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (index, slot) in old.iter().enumerate().filter(|(i, _)| !claimed[*i]) {
+            unclaimed.entry(slot.key).or_default().push(index);
+        }
+        for (index, slot) in slots.iter().enumerate() {
+            if placed[index].is_some() {
+                continue;
+            }
+            // Glyphs of the same section move when the atlas is repacked.
+            let candidates = unclaimed.get_mut(&slot.key);
+            if let Some(candidates) = candidates
+                && let Some(position) = candidates.iter().position(|&old_index| {
+                    bytes(&old_vertices[old[old_index].vertices.clone()])
+                        == bytes(&vertices[slot.vertices.clone()])
+                })
+            {
+                let old_index = candidates.swap_remove(position);
+                claimed[old_index] = true;
+                placed[index] = Some(&old[old_index]);
+            }
+        }
+    }
+
+    // Changed sections are rewritten in place if they fit, otherwise appended.
+    let mut end = old_end;
+    let mut dirty = Vec::new();
+    for (index, slot) in slots.iter_mut().enumerate() {
+        let len = slot.vertices.len();
+        (slot.offset, slot.capacity) = match placed[index] {
+            Some(old_slot) => (old_slot.offset, old_slot.capacity),
+            None => {
+                dirty.push(index);
+                match old.get(index) {
+                    Some(old_slot) if !claimed[index] && old_slot.capacity >= len => {
+                        claimed[index] = true;
+                        (old_slot.offset, old_slot.capacity)
+                    }
+                    _ => {
+                        let capacity = len.next_multiple_of(SLOT_ALIGNMENT);
+                        end += capacity;
+                        (end - capacity, capacity)
+                    }
+                }
+            }
+        };
+    }
+    (dirty, end)
+}
+
+/// Returns `true` if section slots ending at `end` don't fit the `capacity` of the
+/// vertex buffer, or waste too much of it for `used` vertices.
+fn wastes_slots(end: usize, used: usize, capacity: usize) -> bool {
+    end > capacity || end - used > used.max(MIN_COMPACTION_WASTE)
+}
+
+/// Render bundle together with the bind groups and immediate data it was recorded
+/// with.
+#[derive(Debug)]
+struct RecordedBundle {
+    bundle: wgpu::RenderBundle,
+    bind_groups: Vec<wgpu::BindGroup>,
+    immediates: Vec<u8>,
+}
+
+impl RecordedBundle {
+    /// Returns `true` if the bundle was recorded with the bind groups and immediate
+    /// data of the `options`.
+    fn matches(&self, options: &DrawOptions) -> bool {
+        self.immediates == options.immediates
+            && self.bind_groups.len() == options.bind_groups.len()
+            && self
+                .bind_groups
+                .iter()
+                .zip(options.bind_groups)
+                .all(|(recorded, &group)| recorded == group)
     }
 }
 
+/// Range of instances drawn with one section style from one atlas page.
+#[derive(Debug)]
+struct StyleRun {
+    layer: u32,
+    style: u32,
+    page: u32,
+    instances: Range<u32>,
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
@@ -147,6 +950,8 @@ pub struct Vertex {
     tex_top_left: [f32; 2],
     tex_bottom_right: [f32; 2],
     color: [f32; 4],
+    /// Glyph offset, scale and rotation.
+    transform: [f32; 4],
 }
 
 impl Vertex {
@@ -156,12 +961,17 @@ impl Vertex {
             pixel_coords,
             bounds,
             extra,
-        }: glyph_brush::GlyphVertex,
+        }: glyph_brush::GlyphVertex<SectionExtra>,
     ) -> Vertex {
         let mut rect = Rect {
             min: point(pixel_coords.min.x, pixel_coords.min.y),
             max: point(pixel_coords.max.x, pixel_coords.max.y),
         };
+        if extra.grid.snap_quads {
+            let offset = point(rect.min.x.round(), rect.min.y.round()) - rect.min;
+            rect.min += offset;
+            rect.max += offset;
+        }
 
         // handle overlapping bounds, modify uv_rect to preserve texture aspect
         if rect.max.x > bounds.max.x {
@@ -195,9 +1005,29 @@ impl Vertex {
             tex_top_left: [tex_coords.min.x, tex_coords.min.y],
             tex_bottom_right: [tex_coords.max.x, tex_coords.max.y],
             color: extra.color,
+            transform: GlyphTransform::identity(extra.color).to_array(),
         }
     }
 
+    /// Creates an untransformed quad showing the whole cache texture.
+    pub fn quad(top_left: [f32; 2], size: [f32; 2], color: [f32; 4]) -> Vertex {
+        Vertex {
+            top_left: [top_left[0], top_left[1], 0.0],
+            bottom_right: [top_left[0] + size[0], top_left[1] + size[1]],
+            tex_top_left: [0.0, 0.0],
+            tex_bottom_right: [1.0, 1.0],
+            color,
+            transform: GlyphTransform::identity(color).to_array(),
+        }
+    }
+
+    /// Returns the normalized texture coordinates of the glyph as
+    /// `[left, top, right, bottom]`.
+    pub fn tex_rect(&self) -> [f32; 4] {
+        let ([left, top], [right, bottom]) = (self.tex_top_left, self.tex_bottom_right);
+        [left, top, right, bottom]
+    }
+
     pub fn buffer_layout() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
@@ -228,7 +1058,206 @@ impl Vertex {
                     offset: std::mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
                     shader_location: 4,
                 },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 13]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                },
             ],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(key: u64, x: f32) -> GlyphQuad {
+        GlyphQuad {
+            vertex: Vertex::quad([x, 0.0], [1.0, 1.0], [1.0; 4]),
+            section: key,
+            page: 0,
+        }
+    }
+
+    fn queued(keys: &[u64]) -> Vec<QueuedSection> {
+        keys.iter()
+            .map(|&key| QueuedSection {
+                key,
+                style: 0,
+                layer: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ranges_of_sections() {
+        let quads: Vec<_> = [1, 1, 2, 2, 2].map(|key| quad(key, 0.0)).into();
+        assert_eq!(section_ranges(&quads, &queued(&[1, 2])), [0..2, 2..5]);
+
+        // Sections without glyphs get empty ranges.
+        let quads: Vec<_> = [1, 2].map(|key| quad(key, 0.0)).into();
+        assert_eq!(
+            section_ranges(&quads, &queued(&[3, 1, 4, 2, 5])),
+            [0..0, 0..1, 1..1, 1..2, 2..2]
+        );
+    }
+
+    #[test]
+    fn ranges_of_twins() {
+        // Identical sections queued right after each other share their quads.
+        let quads: Vec<_> = [1, 1, 1, 1, 2].map(|key| quad(key, 0.0)).into();
+        assert_eq!(
+            section_ranges(&quads, &queued(&[1, 1, 2])),
+            [0..2, 2..4, 4..5]
+        );
+        let quads: Vec<_> = [1; 5].map(|key| quad(key, 0.0)).into();
+        assert_eq!(section_ranges(&quads, &queued(&[1, 1])), [0..2, 2..5]);
+
+        // Duplicates queued apart from each other take their own quads.
+        let quads: Vec<_> = [1, 1, 2, 1, 1].map(|key| quad(key, 0.0)).into();
+        assert_eq!(
+            section_ranges(&quads, &queued(&[1, 2, 1])),
+            [0..2, 2..3, 3..5]
+        );
+    }
+
+    /// Section slots of the last placed frame.
+    #[derive(Default)]
+    struct Slots {
+        slots: Vec<SectionSlot>,
+        vertices: Vec<Vertex>,
+        end: usize,
+    }
+
+    impl Slots {
+        /// Places the sections with the provided keys and glyph positions, returning
+        /// the indices of the sections which have to be written.
+        fn place(&mut self, sections: &[(u64, &[f32])]) -> Vec<usize> {
+            let mut vertices = Vec::new();
+            let mut slots: Vec<_> = sections
+                .iter()
+                .enumerate()
+                .map(|(index, &(key, glyphs))| {
+                    let start = vertices.len();
+                    vertices.extend(glyphs.iter().map(|&x| quad(key, x).vertex));
+                    SectionSlot {
+                        vertices: start..vertices.len(),
+                        offset: 0,
+                        capacity: 0,
+                        section: index as u32,
+                        key,
+                        style: 0,
+                        layer: 0,
+                        page: 0,
+                    }
+                })
+                .collect();
+            let (dirty, end) = place_sections(
+                &self.slots,
+                &self.vertices,
+                self.end,
+                &vertices,
+                &mut slots,
+            );
+            *self = Slots {
+                slots,
+                vertices,
+                end,
+            };
+            dirty
+        }
+
+        fn offsets(&self) -> Vec<usize> {
+            self.slots.iter().map(|slot| slot.offset).collect()
+        }
+    }
+
+    const A: &[f32] = &[0.0, 1.0, 2.0];
+    const B: &[f32] = &[0.0, 1.0, 2.0, 3.0, 4.0];
+    const C: &[f32] = &[9.0];
+
+    #[test]
+    fn unchanged_sections_keep_their_slots() {
+        let mut slots = Slots::default();
+        assert_eq!(slots.place(&[(1, A), (2, B)]), [0, 1]);
+        assert_eq!((slots.offsets(), slots.end), (vec![0, 8], 16));
+
+        assert!(slots.place(&[(1, A), (2, B)]).is_empty());
+        assert_eq!((slots.offsets(), slots.end), (vec![0, 8], 16));
+    }
+
+    #[test]
+    fn added_and_removed_sections() {
+        let mut slots = Slots::default();
+        slots.place(&[(1, A), (2, B)]);
+
+        // Only the added section is written, after the others.
+        assert_eq!(slots.place(&[(3, C), (1, A), (2, B)]), [0]);
+        assert_eq!((slots.offsets(), slots.end), (vec![16, 0, 8], 24));
+
+        // Removing sections leaves holes until compacting.
+        assert!(slots.place(&[(2, B)]).is_empty());
+        assert_eq!((slots.offsets(), slots.end), (vec![8], 24));
+        assert!(!wastes_slots(slots.end, B.len(), 64));
+        assert!(wastes_slots(slots.end, B.len(), 16));
+    }
+
+    #[test]
+    fn changed_sections() {
+        let mut slots = Slots::default();
+        slots.place(&[(1, A), (2, B)]);
+
+        // Rewritten in place while fitting their slot.
+        assert_eq!(slots.place(&[(1, &[5.0, 6.0]), (2, B)]), [0]);
+        assert_eq!((slots.offsets(), slots.end), (vec![0, 8], 16));
+
+        // Moved to the end otherwise.
+        let long = [0.0; 9];
+        assert_eq!(slots.place(&[(1, &long), (2, B)]), [0]);
+        assert_eq!((slots.offsets(), slots.end), (vec![16, 8], 32));
+        assert_eq!(slots.slots[0].capacity, 16);
+    }
+
+    #[test]
+    fn duplicate_sections() {
+        let mut slots = Slots::default();
+        assert_eq!(slots.place(&[(1, A), (1, A)]), [0, 1]);
+        assert_eq!(slots.offsets(), [0, 8]);
+        assert!(slots.place(&[(1, A), (1, A)]).is_empty());
+
+        // Each duplicate keeps its own slot when a section is added before them.
+        assert_eq!(slots.place(&[(3, C), (1, A), (1, A)]), [0]);
+        let mut offsets = slots.offsets();
+        assert_eq!(offsets.remove(0), 16);
+        offsets.sort();
+        assert_eq!(offsets, [0, 8]);
+
+        // And one of them stays when the other is removed.
+        assert!(slots.place(&[(1, A)]).is_empty());
+        assert!([0, 8].contains(&slots.offsets()[0]));
+    }
+
+    #[test]
+    fn compaction() {
+        // Always tolerated below the minimum waste.
+        assert!(!wastes_slots(MIN_COMPACTION_WASTE, 0, MIN_COMPACTION_WASTE));
+        assert!(wastes_slots(MIN_COMPACTION_WASTE + 1, 0, 2048));
+        // Otherwise once more vertices are wasted than used.
+        assert!(!wastes_slots(4000, 2000, 4000));
+        assert!(wastes_slots(4001, 2000, 8000));
+        // Slots beyond the capacity are always compacted.
+        assert!(wastes_slots(101, 100, 100));
+
+        let mut slots = Slots::default();
+        let glyphs = [0.0; 200];
+        let sections: Vec<(u64, &[f32])> =
+            (0..10).map(|key| (key, &glyphs[..])).collect();
+        slots.place(&sections);
+        assert!(!wastes_slots(slots.end, 2000, 2000));
+        slots.place(&sections[..6]);
+        assert!(!wastes_slots(slots.end, 1200, 2000));
+        slots.place(&sections[..1]);
+        assert!(wastes_slots(slots.end, 200, 2000));
+    }
+}
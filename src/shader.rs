@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+
+use crate::{cache::Uniforms, error::BrushError, pipeline::Vertex, style::StyleUniforms};
+
+/// WGSL source of the built-in text shader.
+///
+/// Can be used as a starting point when writing a custom shader for
+/// [`BrushBuilder::with_shader()`](crate::BrushBuilder::with_shader).
+pub const DEFAULT_SHADER: &str = concat!(
+    include_str!("shader/shader.wgsl"),
+    include_str!("shader/fragment.wgsl")
+);
+
+/// Shader of the debug overlay, see [`TextBrush::draw_debug()`](crate::TextBrush::draw_debug).
+pub(crate) const DEBUG_SHADER: &str = concat!(
+    include_str!("shader/shader.wgsl"),
+    include_str!("shader/debug.wgsl")
+);
+
+/// Built-in shader without the `text_fragment` function.
+const SHADER_PRELUDE: &str = include_str!("shader/shader.wgsl");
+
+const VERTEX_ENTRY_POINT: &str = "vs_main";
+const FRAGMENT_ENTRY_POINT: &str = "fs_main";
+
+/// Validates a user provided shader module which replaces the built-in one.
+///
+/// Validates with all capabilities since the device isn't known yet, see
+/// [`check_pipeline()`].
+pub fn custom_module(source: Cow<'static, str>) -> Result<Cow<'static, str>, BrushError> {
+    let (module, _) = validate(&source, naga::valid::Capabilities::all())?;
+
+    for (name, stage) in [
+        (VERTEX_ENTRY_POINT, naga::ShaderStage::Vertex),
+        (FRAGMENT_ENTRY_POINT, naga::ShaderStage::Fragment),
+    ] {
+        if !module
+            .entry_points
+            .iter()
+            .any(|entry_point| entry_point.name == name && entry_point.stage == stage)
+        {
+            return Err(invalid(format!(
+                "missing the '{name}' {stage:?} entry point"
+            )));
+        }
+    }
+
+    Ok(source)
+}
+
+/// Injects a user provided `text_fragment` function into the built-in shader.
+///
+/// A missing `text_fragment` function is reported by the parser since
+/// `fs_main` of the built-in shader calls it.
+pub fn inject_fragment(source: &str) -> Result<Cow<'static, str>, BrushError> {
+    let shader = format!("{SHADER_PRELUDE}\n{source}");
+    validate(&shader, naga::valid::Capabilities::all())?;
+
+    Ok(Cow::Owned(shader))
+}
+
+/// Checks the shader against the `device` and the pipeline it's used in, reporting
+/// everything that would otherwise fail while creating the pipeline.
+///
+/// Validates the module with the capabilities of the device features, and checks
+/// the used bindings against the built-in and the `custom_groups` registered bind
+/// group layouts, the immediate data against `immediate_size`, the vertex inputs
+/// against [`Vertex::buffer_layout()`] and the fragment inputs against the vertex
+/// outputs.
+pub fn check_pipeline(
+    source: &str,
+    device: &wgpu::Device,
+    custom_groups: usize,
+    immediate_size: u32,
+) -> Result<(), BrushError> {
+    // Downlevel flags are only known by the adapter, so none of them are assumed.
+    let capabilities = wgpu_naga_bridge::features_to_naga_capabilities(
+        device.features(),
+        wgpu::DownlevelFlags::empty(),
+    );
+    let (module, info) = validate(source, capabilities)?;
+    let [vertex, fragment] = [VERTEX_ENTRY_POINT, FRAGMENT_ENTRY_POINT].map(|name| {
+        module
+            .entry_points
+            .iter()
+            .position(|entry_point| entry_point.name == name)
+            .ok_or_else(|| invalid(format!("missing the '{name}' entry point")))
+    });
+    let (vertex, fragment) = (vertex?, fragment?);
+
+    for (handle, global) in module.global_variables.iter() {
+        if [vertex, fragment]
+            .iter()
+            .all(|&index| info.get_entry_point(index)[handle].is_empty())
+        {
+            continue;
+        }
+        let name = global.name.as_deref().unwrap_or("_");
+        let ty = &module.types[global.ty].inner;
+        let size = ty.size(module.to_ctx());
+
+        if global.space == naga::AddressSpace::Immediate && size > immediate_size {
+            return Err(invalid(format!(
+                "the immediate data '{name}' needs {size} bytes, but the immediate \
+                size is {immediate_size} bytes"
+            )));
+        }
+        let Some(naga::ResourceBinding { group, binding }) = global.binding else {
+            continue;
+        };
+        let matches = match (group, binding) {
+            (0, 0) => uniform_fits::<Uniforms>(global.space, size),
+            (0, 3) => uniform_fits::<StyleUniforms>(global.space, size),
+            (0, 1) => matches!(
+                ty,
+                naga::TypeInner::Image {
+                    dim: naga::ImageDimension::D2,
+                    arrayed: false,
+                    class: naga::ImageClass::Sampled {
+                        kind: naga::ScalarKind::Float,
+                        multi: false,
+                    },
+                }
+            ),
+            (0, 2) => matches!(ty, naga::TypeInner::Sampler { comparison: false }),
+            (0, _) => false,
+            // Registered layouts are opaque, so only their existence is checked.
+            (group, _) => group as usize <= custom_groups,
+        };
+        if !matches {
+            return Err(invalid(format!(
+                "'{name}' at @group({group}) @binding({binding}) doesn't match a bind \
+                group layout entry of the brush"
+            )));
+        }
+    }
+
+    let vertex = &module.entry_points[vertex].function;
+    let mut inputs = Vec::new();
+    for argument in &vertex.arguments {
+        locations(&module, argument.ty, argument.binding.as_ref(), &mut inputs);
+    }
+    let attributes = Vertex::buffer_layout().attributes;
+    for (location, ty) in inputs {
+        if !attributes
+            .iter()
+            .any(|attribute| attribute.shader_location == location)
+            || scalar(ty) != Some(naga::Scalar::F32)
+        {
+            return Err(invalid(format!(
+                "the '{VERTEX_ENTRY_POINT}' input at @location({location}) doesn't \
+                match a vertex attribute of the brush"
+            )));
+        }
+    }
+
+    let mut outputs = Vec::new();
+    if let Some(result) = &vertex.result {
+        locations(&module, result.ty, result.binding.as_ref(), &mut outputs);
+    }
+    let fragment = &module.entry_points[fragment].function;
+    let mut inputs = Vec::new();
+    for argument in &fragment.arguments {
+        locations(&module, argument.ty, argument.binding.as_ref(), &mut inputs);
+    }
+    for (location, ty) in inputs {
+        if !outputs.contains(&(location, ty)) {
+            return Err(invalid(format!(
+                "the '{FRAGMENT_ENTRY_POINT}' input at @location({location}) doesn't \
+                match an output of '{VERTEX_ENTRY_POINT}'"
+            )));
+        }
+    }
+
+    let mut targets = Vec::new();
+    if let Some(result) = &fragment.result {
+        locations(&module, result.ty, result.binding.as_ref(), &mut targets);
+    }
+    for (location, ty) in targets {
+        if location != 0
+            || scalar(ty).map(|scalar| scalar.kind) != Some(naga::ScalarKind::Float)
+        {
+            return Err(invalid(format!(
+                "the '{FRAGMENT_ENTRY_POINT}' output at @location({location}) doesn't \
+                match the single float color target of the brush"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate(
+    source: &str,
+    capabilities: naga::valid::Capabilities,
+) -> Result<(naga::Module, naga::valid::ModuleInfo), BrushError> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|err| invalid(err.emit_to_string(source)))?;
+
+    let info =
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), capabilities)
+            .validate(&module)
+            .map_err(|err| invalid(err.emit_to_string(source)))?;
+
+    Ok((module, info))
+}
+
+fn invalid(message: String) -> BrushError {
+    BrushError::InvalidShader(message)
+}
+
+/// Returns `true` if a uniform of `size` bytes fits into the built-in uniform `U`.
+fn uniform_fits<U>(space: naga::AddressSpace, size: u32) -> bool {
+    space == naga::AddressSpace::Uniform && size as usize <= std::mem::size_of::<U>()
+}
+
+/// Collects the types of the entry point arguments or results with a location.
+fn locations<'a>(
+    module: &'a naga::Module,
+    ty: naga::Handle<naga::Type>,
+    binding: Option<&naga::Binding>,
+    found: &mut Vec<(u32, &'a naga::TypeInner)>,
+) {
+    match (binding, &module.types[ty].inner) {
+        (Some(naga::Binding::Location { location, .. }), inner) => {
+            found.push((*location, inner))
+        }
+        (None, naga::TypeInner::Struct { members, .. }) => {
+            for member in members {
+                locations(module, member.ty, member.binding.as_ref(), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn scalar(ty: &naga::TypeInner) -> Option<naga::Scalar> {
+    match *ty {
+        naga::TypeInner::Scalar(scalar) | naga::TypeInner::Vector { scalar, .. } => {
+            Some(scalar)
+        }
+        _ => None,
+    }
+}
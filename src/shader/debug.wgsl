@@ -0,0 +1,9 @@
+// Draws the atlas pages white on a background of the vertex color.
+fn text_fragment(in: VertexOutput, coverage: f32) -> vec4<f32> {
+    return mix(in.color, vec4<f32>(1.0), coverage);
+}
+
+@fragment
+fn fs_outline(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+}
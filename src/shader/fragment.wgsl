@@ -0,0 +1,4 @@
+fn text_fragment(in: VertexOutput, coverage: f32) -> vec4<f32> {
+    let color = in.color * section_fill(in.position);
+    return vec4<f32>(color.rgb * uniforms.luminance * style.luminance, color.a * coverage);
+}
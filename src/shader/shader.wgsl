@@ -5,19 +5,38 @@ struct VertexInput {
     @location(2) tex_top_left: vec2<f32>,
     @location(3) tex_bottom_right: vec2<f32>,
     @location(4) color: vec4<f32>,
+    @location(5) transform: vec4<f32>,
 }
 
-struct Matrix {
-    v: mat4x4<f32>,
+struct Uniforms {
+    matrix: mat4x4<f32>,
+    luminance: f32,
+    viewport: vec2<f32>,
+    billboard_right: vec4<f32>,
+    billboard_up: vec4<f32>,
 }
 
 @group(0) @binding(0)
-var<uniform> ortho: Matrix;
+var<uniform> uniforms: Uniforms;
+
+struct Style {
+    transform: mat4x4<f32>,
+    fill_colors: array<vec4<f32>, 2>,
+    fill_points: vec4<f32>,
+    billboard: vec4<f32>,
+    fill_kind: u32,
+    placement: u32,
+    luminance: f32,
+}
+
+@group(0) @binding(3)
+var<uniform> style: Style;
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_pos: vec2<f32>,
     @location(1) color: vec4<f32>,
+    @location(2) position: vec2<f32>,
 }
 
 @vertex
@@ -54,11 +73,64 @@ fn vs_main(in: VertexInput) -> VertexOutput {
         default: {}
     }
 
-    out.clip_position = ortho.v * vec4<f32>(pos, in.top_left.z, 1.0);
+    // Per-glyph offset, scale and rotation around the glyph center.
+    let center = (in.top_left.xy + in.bottom_right) * 0.5;
+    let scaled = (pos - center) * in.transform.z;
+    let s = sin(in.transform.w);
+    let c = cos(in.transform.w);
+    pos = center + in.transform.xy + vec2<f32>(
+        scaled.x * c - scaled.y * s,
+        scaled.x * s + scaled.y * c
+    );
+
+    let local = style.transform * vec4<f32>(pos, in.top_left.z, 1.0);
+    switch (style.placement) {
+        // Screen sized billboard
+        case 1u: {
+            out.clip_position = uniforms.matrix * vec4<f32>(style.billboard.xyz, 1.0);
+            out.clip_position += vec4<f32>(
+                local.xy * 2.0 / uniforms.viewport * out.clip_position.w,
+                0.0,
+                0.0
+            );
+        }
+        // World sized billboard
+        case 2u: {
+            let world = style.billboard.xyz + (uniforms.billboard_right.xyz * local.x
+                + uniforms.billboard_up.xyz * local.y) * style.billboard.w;
+            out.clip_position = uniforms.matrix * vec4<f32>(world, 1.0);
+        }
+        default: {
+            out.clip_position = uniforms.matrix * local;
+        }
+    }
     out.color = in.color;
+    out.position = pos;
     return out;
 }
 
+// Returns the section fill color at the provided position.
+fn section_fill(position: vec2<f32>) -> vec4<f32> {
+    var t: f32;
+    switch (style.fill_kind) {
+        // Linear gradient
+        case 1u: {
+            let start = style.fill_points.xy;
+            let direction = style.fill_points.zw - start;
+            t = dot(position - start, direction) / max(dot(direction, direction), 1e-6);
+        }
+        // Radial gradient
+        case 2u: {
+            t = distance(position, style.fill_points.xy) / max(style.fill_points.z, 1e-6);
+        }
+        default: {
+            return vec4<f32>(1.0);
+        }
+    }
+
+    return mix(style.fill_colors[0], style.fill_colors[1], clamp(t, 0.0, 1.0));
+}
+
 @group(0) @binding(1)
 var texture: texture_2d<f32>;
 @group(0) @binding(2)
@@ -66,7 +138,7 @@ var tex_sampler: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var alpha: f32 = textureSample(texture, tex_sampler, in.tex_pos).r;
+    var coverage: f32 = textureSample(texture, tex_sampler, in.tex_pos).r;
 
-    return vec4<f32>(in.color.rgb, in.color.a * alpha);
+    return text_fragment(in, coverage);
 }
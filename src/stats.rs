@@ -0,0 +1,40 @@
+/// Statistics of the last processed frame of a [`TextBrush`](crate::TextBrush),
+/// see [`TextBrush::stats()`](crate::TextBrush::stats).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BrushStats {
+    /// Sections queued for the last processing.
+    pub sections_queued: u32,
+    /// Glyphs with a visible outline in the queued sections.
+    pub glyphs_queued: u32,
+    /// Whether the last processing reused the vertices of the previous frame.
+    /// Always `false` for brushes sharing their atlas, see
+    /// [redrawing](crate::GlyphAtlas#redrawing).
+    pub redraw: bool,
+
+    /// Glyphs rasterized and uploaded into the atlas by the last processing. When
+    /// the atlas was repacked, all glyphs are uploaded at once and counted as one.
+    pub glyphs_uploaded: u32,
+    /// Bytes of glyph bitmaps and vertices uploaded by the last processing.
+    pub bytes_uploaded: u64,
+    /// Times the cache textures were resized, trimmed or a page was added by the
+    /// last processing.
+    pub texture_resizes: u32,
+    /// Times the vertex buffer was reallocated by the last processing.
+    pub vertex_reallocations: u32,
+
+    /// Glyph instances drawn since the last processing, summed over all draw calls.
+    /// Render bundles and the debug overlay aren't counted.
+    pub instances_drawn: u64,
+    /// Draw calls issued since the last processing.
+    pub draw_calls: u32,
+
+    /// Cache texture dimensions, those of the first page with multiple pages.
+    pub atlas_dimensions: (u32, u32),
+    /// Number of atlas pages.
+    pub atlas_pages: usize,
+    /// Memory used by the cache textures of all pages, in bytes.
+    pub atlas_bytes: u64,
+    /// Estimated fraction of the cache textures used by the glyphs of all brushes
+    /// sharing the atlas, from `0.0` to `1.0`.
+    pub atlas_occupancy: f32,
+}
@@ -0,0 +1,522 @@
+use std::{
+    borrow::Cow,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use glyph_brush::{
+    Color, OwnedSection, Section, Text,
+    ab_glyph::{Point, PxScale, point},
+};
+
+use crate::{
+    Matrix,
+    grid::{GridLayout, PixelGrid},
+};
+
+/// Fill of the glyphs in a section.
+///
+/// Gradient colors are multiplied with the text colors, so use white text
+/// ([`glyph_brush::Text::with_color()`]) for the exact gradient colors.
+///
+/// All points are in *section space*, which has its origin at the section
+/// `screen_position` and uses the same units as the section layout (pixels).
+/// The gradient spans the whole section instead of each glyph separately.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Fill {
+    /// Glyphs are filled only with the text colors.
+    #[default]
+    Solid,
+    /// Colors are interpolated along the line from `start` to `end`.
+    LinearGradient {
+        start: (f32, f32),
+        end: (f32, f32),
+        colors: [Color; 2],
+    },
+    /// Colors are interpolated from the `center` outwards to the `radius`.
+    RadialGradient {
+        center: (f32, f32),
+        radius: f32,
+        colors: [Color; 2],
+    },
+}
+
+/// 2D affine transformation of a section, applied to its glyph quads.
+///
+/// The section is scaled, skewed and then rotated around the `anchor`, which is
+/// in section space (relative to the section `screen_position`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectionTransform {
+    /// Point around which the section is transformed.
+    pub anchor: (f32, f32),
+    /// Clockwise rotation in radians.
+    pub rotation: f32,
+    /// Horizontal and vertical scale factors.
+    pub scale: (f32, f32),
+    /// Horizontal and vertical skew angles in radians.
+    pub skew: (f32, f32),
+}
+
+impl Default for SectionTransform {
+    fn default() -> Self {
+        Self {
+            anchor: (0.0, 0.0),
+            rotation: 0.0,
+            scale: (1.0, 1.0),
+            skew: (0.0, 0.0),
+        }
+    }
+}
+
+impl SectionTransform {
+    /// Creates a transformation which rotates the section by `rotation` radians
+    /// around the section `screen_position`.
+    pub fn rotation(rotation: f32) -> Self {
+        Self {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the point around which the section is transformed.
+    pub fn with_anchor(mut self, anchor: (f32, f32)) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Sets the clockwise rotation in radians.
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Sets the horizontal and vertical scale factors.
+    pub fn with_scale(mut self, scale: (f32, f32)) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Sets the horizontal and vertical skew angles in radians.
+    pub fn with_skew(mut self, skew: (f32, f32)) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    /// Transforms the `point` of a section positioned at `origin`.
+    pub fn transform_point(&self, origin: (f32, f32), p: Point) -> Point {
+        let [[a, b, ..], [c, d, ..], _, [tx, ty, ..]] = self.to_matrix(origin);
+        point(a * p.x + c * p.y + tx, b * p.x + d * p.y + ty)
+    }
+
+    /// Column-major matrix of the transformation for a section positioned at `origin`.
+    pub(crate) fn to_matrix(self, origin: (f32, f32)) -> Matrix {
+        let (sin, cos) = self.rotation.sin_cos();
+        let (skew_x, skew_y) = (self.skew.0.tan(), self.skew.1.tan());
+        let (scale_x, scale_y) = self.scale;
+
+        // Rotation * Skew * Scale
+        let a = (cos - sin * skew_y) * scale_x;
+        let b = (sin + cos * skew_y) * scale_x;
+        let c = (cos * skew_x - sin) * scale_y;
+        let d = (sin * skew_x + cos) * scale_y;
+
+        let anchor_x = origin.0 + self.anchor.0;
+        let anchor_y = origin.1 + self.anchor.1;
+        let tx = anchor_x - (a * anchor_x + c * anchor_y);
+        let ty = anchor_y - (b * anchor_x + d * anchor_y);
+
+        [
+            [a, b, 0.0, 0.0],
+            [c, d, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [tx, ty, 0.0, 1.0],
+        ]
+    }
+}
+
+/// Size of a [`Placement::Billboard`] section.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BillboardSize {
+    /// Layout units correspond to screen pixels, regardless of the distance.
+    ///
+    /// Requires an up-to-date viewport, see
+    /// [`TextBrush::update_viewport()`](crate::TextBrush::update_viewport).
+    #[default]
+    Screen,
+    /// Each layout unit corresponds to the provided number of world units,
+    /// so the section shrinks with distance.
+    ///
+    /// Requires up-to-date camera axes, see
+    /// [`TextBrush::update_billboard_axes()`](crate::TextBrush::update_billboard_axes).
+    World(f32),
+}
+
+/// Placement of a section in the scene.
+///
+/// For placing sections in a 3D world, the brush matrix should be the
+/// view-projection matrix of the camera ([`TextBrush::update_matrix()`](crate::TextBrush::update_matrix)).
+/// Occlusion with other geometry is achieved with depth testing
+/// ([`BrushBuilder::with_depth_stencil()`](crate::BrushBuilder::with_depth_stencil)).
+///
+/// In [`Placement::World`] and [`Placement::Billboard`] modes, the section layout is
+/// converted to a local space with the origin at the section `screen_position`,
+/// `x` pointing right and `y` pointing up.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Placement {
+    /// The section is positioned by its layout and the brush matrix.
+    #[default]
+    Screen,
+    /// The section local space is transformed to world space with the
+    /// provided model matrix.
+    World(Matrix),
+    /// The section always faces the camera and is anchored at a world point.
+    Billboard {
+        /// World position of the section `screen_position`.
+        anchor: [f32; 3],
+        /// Size of the section.
+        size: BillboardSize,
+    },
+}
+
+/// Per-section rendering settings which aren't part of the section layout.
+///
+/// Sections are queued with a style by passing a `(section, style)` tuple or
+/// a [`StyledSection`] to [`TextBrush::queue()`](crate::TextBrush::queue) or
+/// [`TextBrush::queue_section()`](crate::TextBrush::queue_section).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct SectionStyle {
+    /// Fill of the section glyphs. Defaults to [`Fill::Solid`].
+    pub fill: Fill,
+    /// Transformation of the section glyph quads. Defaults to identity.
+    pub transform: SectionTransform,
+    /// Placement of the section in the scene. Defaults to [`Placement::Screen`].
+    pub placement: Placement,
+    /// Draw layer of the section, drawn with
+    /// [`TextBrush::draw_layer()`](crate::TextBrush::draw_layer). Defaults to `0`.
+    pub layer: u32,
+    /// Multiplier of the RGB color components of the section glyphs, applied on
+    /// top of the brush luminance
+    /// ([`BrushBuilder::with_luminance()`](crate::BrushBuilder::with_luminance)).
+    /// Defaults to `1.0`.
+    pub luminance: f32,
+}
+
+impl Default for SectionStyle {
+    fn default() -> Self {
+        Self {
+            fill: Fill::default(),
+            transform: SectionTransform::default(),
+            placement: Placement::default(),
+            layer: 0,
+            luminance: 1.0,
+        }
+    }
+}
+
+impl SectionStyle {
+    /// Sets the fill of the section glyphs.
+    pub fn with_fill(mut self, fill: Fill) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Sets the transformation of the section glyph quads.
+    pub fn with_transform(mut self, transform: SectionTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Sets the placement of the section in the scene.
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Sets the draw layer of the section.
+    pub fn with_layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    /// Sets the luminance multiplier of the section, for example to make a single
+    /// highlight brighter than the rest of the text on an HDR render target.
+    pub fn with_luminance(mut self, luminance: f32) -> Self {
+        self.luminance = luminance;
+        self
+    }
+
+    /// Converts the style of a section at the logical `origin`, laid out in device
+    /// pixels scaled by the `scale_factor`.
+    pub(crate) fn to_uniforms(
+        self,
+        origin: (f32, f32),
+        scale_factor: f32,
+    ) -> StyleUniforms {
+        let (fill_kind, fill_points, fill_colors) = match self.fill {
+            Fill::Solid => (0, [0.0; 4], [[1.0; 4]; 2]),
+            Fill::LinearGradient { start, end, colors } => (
+                1,
+                [
+                    origin.0 + start.0,
+                    origin.1 + start.1,
+                    origin.0 + end.0,
+                    origin.1 + end.1,
+                ],
+                colors,
+            ),
+            Fill::RadialGradient {
+                center,
+                radius,
+                colors,
+            } => (
+                2,
+                [origin.0 + center.0, origin.1 + center.1, radius, 0.0],
+                colors,
+            ),
+        };
+        // Gradients are evaluated in the layout space.
+        let fill_points = fill_points.map(|value| value * scale_factor);
+
+        // The transformation is applied in logical pixels, converting the layout
+        // back to them first.
+        let transform = multiply(
+            self.transform.to_matrix(origin),
+            scaling(1.0 / scale_factor),
+        );
+        // Converts the layout into local space with `y` pointing up.
+        let local = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, -1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [-origin.0, origin.1, 0.0, 1.0],
+        ];
+        let device = scaling(scale_factor);
+        let (transform, placement, billboard) = match self.placement {
+            Placement::Screen => (multiply(device, transform), 0, [0.0; 4]),
+            Placement::World(model) => {
+                (multiply(model, multiply(local, transform)), 0, [0.0; 4])
+            }
+            Placement::Billboard { anchor, size } => {
+                let (placement, local, scale) = match size {
+                    BillboardSize::Screen => (1, multiply(device, local), 1.0),
+                    BillboardSize::World(scale) => (2, local, scale),
+                };
+                (
+                    multiply(local, transform),
+                    placement,
+                    [anchor[0], anchor[1], anchor[2], scale],
+                )
+            }
+        };
+
+        StyleUniforms {
+            transform,
+            fill_colors,
+            fill_points,
+            billboard,
+            fill_kind,
+            placement,
+            luminance: self.luminance,
+            _padding: 0,
+        }
+    }
+}
+
+/// Section queued together with its [`SectionStyle`].
+#[derive(Debug, Clone)]
+pub struct StyledSection<'a> {
+    pub section: Cow<'a, Section<'a>>,
+    pub style: SectionStyle,
+}
+
+impl<'a> From<Section<'a>> for StyledSection<'a> {
+    fn from(section: Section<'a>) -> Self {
+        Self {
+            section: Cow::Owned(section),
+            style: SectionStyle::default(),
+        }
+    }
+}
+
+impl<'a> From<&'a Section<'a>> for StyledSection<'a> {
+    fn from(section: &'a Section<'a>) -> Self {
+        Self {
+            section: Cow::Borrowed(section),
+            style: SectionStyle::default(),
+        }
+    }
+}
+
+impl<'a> From<&'a OwnedSection> for StyledSection<'a> {
+    fn from(section: &'a OwnedSection) -> Self {
+        Self {
+            section: section.into(),
+            style: SectionStyle::default(),
+        }
+    }
+}
+
+impl<'a> From<Cow<'a, Section<'a>>> for StyledSection<'a> {
+    fn from(section: Cow<'a, Section<'a>>) -> Self {
+        Self {
+            section,
+            style: SectionStyle::default(),
+        }
+    }
+}
+
+impl<'a, S> From<(S, SectionStyle)> for StyledSection<'a>
+where
+    S: Into<Cow<'a, Section<'a>>>,
+{
+    fn from((section, style): (S, SectionStyle)) -> Self {
+        Self {
+            section: section.into(),
+            style,
+        }
+    }
+}
+
+/// Extra text data used by the inner [`glyph_brush::GlyphBrush`]. Besides the
+/// [`glyph_brush::Extra`] fields, it holds the identity of the section and the
+/// alignment of the glyphs to the pixel grid.
+///
+/// It doesn't depend on the order the sections were queued in, so sections keep
+/// their cached layouts and vertices when sections before them are added or removed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SectionExtra {
+    pub color: Color,
+    pub z: f32,
+    /// Hash of the text, layout and geometry of the section.
+    pub section: u64,
+    pub grid: PixelGrid,
+}
+
+impl SectionExtra {
+    /// Converts the section into one usable by the inner `GlyphBrush`, scaling it
+    /// from logical to device pixels.
+    pub fn section<'a>(
+        section: &Section<'a>,
+        grid: PixelGrid,
+        scale_factor: f32,
+    ) -> Section<'a, SectionExtra> {
+        let (x, y) = section.screen_position;
+        let (width, height) = section.bounds;
+        let mut section = Section {
+            screen_position: (x * scale_factor, y * scale_factor),
+            bounds: (width * scale_factor, height * scale_factor),
+            layout: section.layout,
+            text: section
+                .text
+                .iter()
+                .map(|text| Text {
+                    text: text.text,
+                    scale: PxScale {
+                        x: text.scale.x * scale_factor,
+                        y: text.scale.y * scale_factor,
+                    },
+                    font_id: text.font_id,
+                    extra: SectionExtra {
+                        color: text.extra.color,
+                        z: text.extra.z,
+                        section: 0,
+                        grid,
+                    },
+                })
+                .collect(),
+        };
+
+        let mut hasher = DefaultHasher::new();
+        section.layout.hash(&mut hasher);
+        [section.screen_position, section.bounds]
+            .map(|(x, y)| [x.to_bits(), y.to_bits()])
+            .hash(&mut hasher);
+        for text in &section.text {
+            (
+                text.text,
+                text.font_id,
+                text.scale.x.to_bits(),
+                text.scale.y.to_bits(),
+            )
+                .hash(&mut hasher);
+            text.extra.hash(&mut hasher);
+        }
+        let key = hasher.finish();
+        for text in &mut section.text {
+            text.extra.section = key;
+        }
+        section
+    }
+
+    /// Returns the layout of a converted `section`, aligning the glyphs to the
+    /// pixel grid of its text.
+    pub fn layout(section: &Section<SectionExtra>) -> GridLayout {
+        GridLayout {
+            layout: section.layout,
+            grid: section
+                .text
+                .first()
+                .map_or_else(PixelGrid::default, |text| text.extra.grid),
+        }
+    }
+
+    /// Returns the identity of a converted `section`, `0` if it has no text.
+    pub fn key(section: &Section<SectionExtra>) -> u64 {
+        section.text.first().map_or(0, |text| text.extra.section)
+    }
+}
+
+impl Hash for SectionExtra {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.color.map(f32::to_bits).hash(state);
+        self.z.to_bits().hash(state);
+        self.section.hash(state);
+        self.grid.hash(state);
+    }
+}
+
+impl PartialEq for SectionExtra {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.color.map(f32::to_bits) == other.color.map(f32::to_bits)
+            && self.z.to_bits() == other.z.to_bits()
+            && self.section == other.section
+            && self.grid == other.grid
+    }
+}
+
+/// Per-section uniform data. Bound with a dynamic offset for each section style.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct StyleUniforms {
+    transform: Matrix,
+    fill_colors: [Color; 2],
+    fill_points: [f32; 4],
+    billboard: [f32; 4],
+    fill_kind: u32,
+    placement: u32,
+    luminance: f32,
+    _padding: u32,
+}
+
+/// Returns a matrix scaling the `x` and `y` axes by the `factor`.
+fn scaling(factor: f32) -> Matrix {
+    [
+        [factor, 0.0, 0.0, 0.0],
+        [0.0, factor, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// Multiplies two column-major matrices.
+fn multiply(a: Matrix, b: Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (column, b_column) in result.iter_mut().zip(b) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|i| a[i][row] * b_column[i]).sum();
+        }
+    }
+    result
+}
@@ -0,0 +1,269 @@
+use std::borrow::Cow;
+
+use glyph_brush::Rectangle;
+
+/// How a [`TextBrush`](crate::TextBrush) uploads vertices and glyphs to the GPU,
+/// see [`BrushBuilder::with_upload_mode()`](crate::BrushBuilder::with_upload_mode).
+///
+/// In both modes, only the vertices of the sections which changed since the last
+/// processing are written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UploadMode {
+    /// Writes every vertex range and glyph with [`wgpu::Queue::write_buffer()`] and
+    /// [`wgpu::Queue::write_texture()`].
+    #[default]
+    Queue,
+    /// Records the uploads as copies from a [`wgpu::util::StagingBelt`] into
+    /// a command encoder. All glyphs cached during one processing are uploaded
+    /// from a single staging allocation.
+    ///
+    /// The encoder can be provided with
+    /// [`TextBrush::process_with_encoder()`](crate::TextBrush::process_with_encoder),
+    /// otherwise the brush submits its own encoder when processing.
+    ///
+    /// Vertices of transformed glyphs are recorded the same way, see
+    /// [`TextBrush::transform_glyphs_with_encoder()`](crate::TextBrush::transform_glyphs_with_encoder).
+    /// Uniforms, like the matrix of
+    /// [`TextBrush::update_matrix()`](crate::TextBrush::update_matrix), are still
+    /// written through the queue, which executes the writes before the commands of
+    /// the next submission, including draws recorded before updating them.
+    StagingBelt {
+        /// Size of the staging buffers in bytes. Bigger uploads get their own buffer.
+        chunk_size: wgpu::BufferAddress,
+    },
+}
+
+/// Uploads buffers and textures, counting the uploaded data.
+pub(crate) struct Uploader<'a> {
+    destination: Destination<'a>,
+    /// Glyphs collected for a single staging belt upload.
+    glyph_uploads: Vec<GlyphUpload<'static>>,
+    /// Number of glyphs uploaded so far.
+    pub glyphs: u32,
+    /// Number of bytes uploaded so far.
+    pub bytes: u64,
+}
+
+enum Destination<'a> {
+    Queue(&'a wgpu::Queue),
+    StagingBelt {
+        belt: &'a mut wgpu::util::StagingBelt,
+        encoder: &'a mut wgpu::CommandEncoder,
+    },
+}
+
+impl<'a> Uploader<'a> {
+    pub fn queue(queue: &'a wgpu::Queue) -> Self {
+        Self::new(Destination::Queue(queue))
+    }
+
+    pub fn staging_belt(
+        belt: &'a mut wgpu::util::StagingBelt,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> Self {
+        Self::new(Destination::StagingBelt { belt, encoder })
+    }
+
+    fn new(destination: Destination<'a>) -> Self {
+        Self {
+            destination,
+            glyph_uploads: Vec::new(),
+            glyphs: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl Uploader<'_> {
+    pub fn write_buffer(
+        &mut self,
+        buffer: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[u8],
+    ) {
+        let Some(size) = wgpu::BufferSize::new(data.len() as u64) else {
+            return;
+        };
+        self.bytes += size.get();
+        match &mut self.destination {
+            Destination::Queue(queue) => queue.write_buffer(buffer, offset, data),
+            Destination::StagingBelt { belt, encoder } => {
+                belt.write_buffer(encoder, buffer, offset, size)
+                    .copy_from_slice(data);
+            }
+        }
+    }
+
+    /// Uploads the bitmap of a glyph cached in the `rect` of the single channel
+    /// `texture`, together with its smaller mip levels.
+    ///
+    /// Written right away through the queue, while the glyphs uploaded through a
+    /// staging belt are collected until [`Uploader::flush_glyphs()`].
+    pub fn write_glyph(
+        &mut self,
+        texture: &wgpu::Texture,
+        rect: Rectangle<u32>,
+        data: &[u8],
+    ) {
+        if data.is_empty() {
+            return;
+        }
+        self.glyphs += 1;
+        let mut upload = GlyphUpload {
+            rect,
+            mip_level: 0,
+            data: Cow::Borrowed(data),
+        };
+        loop {
+            self.bytes += upload.data.len() as u64;
+            let next = (upload.mip_level + 1 < texture.mip_level_count())
+                .then(|| upload.downsample());
+            match &mut self.destination {
+                Destination::Queue(queue) => upload.write(queue, texture),
+                Destination::StagingBelt { .. } => {
+                    self.glyph_uploads.push(GlyphUpload {
+                        data: Cow::Owned(upload.data.into_owned()),
+                        ..upload
+                    });
+                }
+            }
+            match next {
+                Some(next) => upload = next,
+                None => break,
+            }
+        }
+    }
+
+    /// Uploads the glyphs collected for the staging belt into the `texture` from
+    /// a single staging allocation.
+    pub fn flush_glyphs(&mut self, texture: &wgpu::Texture) {
+        let glyphs = std::mem::take(&mut self.glyph_uploads);
+        let Destination::StagingBelt { belt, encoder } = &mut self.destination else {
+            return;
+        };
+
+        // Rows of buffer to texture copies have to be aligned.
+        let padded_row = |glyph: &GlyphUpload| {
+            glyph
+                .rect
+                .width()
+                .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        };
+        let size: u32 = glyphs
+            .iter()
+            .map(|glyph| padded_row(glyph) * glyph.rect.height())
+            .sum();
+        let Some(size) = wgpu::BufferSize::new(size as u64) else {
+            return;
+        };
+
+        let slice = belt.allocate(
+            size,
+            wgpu::BufferSize::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64).unwrap(),
+        );
+        let (buffer, base) = (slice.buffer().clone(), slice.offset());
+        let mut view = slice
+            .get_mapped_range_mut()
+            .expect("Failed to map the staging belt buffer");
+
+        let mut offset = 0;
+        for glyph in &glyphs {
+            let (width, padded_row) = (glyph.rect.width(), padded_row(glyph));
+            for (row, data) in glyph.data.chunks_exact(width as usize).enumerate() {
+                let start = offset + row * padded_row as usize;
+                view.slice(start..start + data.len()).copy_from_slice(data);
+            }
+
+            encoder.copy_buffer_to_texture(
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset: base + offset as u64,
+                        bytes_per_row: Some(padded_row),
+                        rows_per_image: Some(glyph.rect.height()),
+                    },
+                },
+                glyph.copy_texture(texture),
+                glyph.extent(),
+            );
+            offset += (padded_row * glyph.rect.height()) as usize;
+        }
+    }
+}
+
+/// Glyph bitmap waiting to be uploaded to the cache texture.
+#[derive(Debug)]
+struct GlyphUpload<'a> {
+    /// Rectangle within the mip level.
+    rect: Rectangle<u32>,
+    mip_level: u32,
+    data: Cow<'a, [u8]>,
+}
+
+impl GlyphUpload<'_> {
+    /// Returns the upload of the next smaller mip level, averaging blocks of 2x2
+    /// texels. The rectangle has to be aligned to 2 texels.
+    fn downsample(&self) -> GlyphUpload<'static> {
+        let (width, height) = (self.rect.width() as usize, self.rect.height() as usize);
+        let mut data = Vec::with_capacity(width / 2 * height / 2);
+        for y in (0..height - height % 2).step_by(2) {
+            let (row, next) = (&self.data[y * width..], &self.data[(y + 1) * width..]);
+            for x in (0..width - width % 2).step_by(2) {
+                let sum = row[x] as u16
+                    + row[x + 1] as u16
+                    + next[x] as u16
+                    + next[x + 1] as u16;
+                data.push(((sum + 2) / 4) as u8);
+            }
+        }
+
+        GlyphUpload {
+            rect: Rectangle {
+                min: self.rect.min.map(|value| value / 2),
+                max: [
+                    self.rect.min[0] / 2 + width as u32 / 2,
+                    self.rect.min[1] / 2 + height as u32 / 2,
+                ],
+            },
+            mip_level: self.mip_level + 1,
+            data: Cow::Owned(data),
+        }
+    }
+
+    fn write(&self, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        queue.write_texture(
+            self.copy_texture(texture),
+            &self.data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(self.rect.width()),
+                rows_per_image: Some(self.rect.height()),
+            },
+            self.extent(),
+        );
+    }
+
+    fn copy_texture<'a>(
+        &self,
+        texture: &'a wgpu::Texture,
+    ) -> wgpu::TexelCopyTextureInfo<'a> {
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: self.mip_level,
+            origin: wgpu::Origin3d {
+                x: self.rect.min[0],
+                y: self.rect.min[1],
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        }
+    }
+
+    fn extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.rect.width(),
+            height: self.rect.height(),
+            depth_or_array_layers: 1,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Saves prewarmed glyphs into atlas files and restores them into new brushes.
+//! Skipped when no adapter is available.
+
+use wgpu_text::{
+    AtlasFileError, BrushBuilder, TextBrush,
+    glyph_brush::{
+        FontId, Section, Text,
+        ab_glyph::{FontRef, PxScale},
+    },
+};
+
+const WIDTH: u32 = 192;
+const HEIGHT: u32 = 48;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const TEXT: &str = "Hello, atlas!";
+const SCALE: f32 = 24.0;
+
+type Brush = TextBrush<FontRef<'static>>;
+
+fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance =
+        wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&Default::default())).ok()?;
+    pollster::block_on(adapter.request_device(&Default::default())).ok()
+}
+
+fn brush(device: &wgpu::Device, font: &'static [u8], mipmaps: bool) -> Brush {
+    BrushBuilder::using_font_bytes(font)
+        .unwrap()
+        .with_mipmaps(mipmaps)
+        .build(device, WIDTH, HEIGHT, FORMAT)
+}
+
+/// Processes the text at an integer position and returns the rendered texels.
+fn render(device: &wgpu::Device, queue: &wgpu::Queue, brush: &mut Brush) -> Vec<u8> {
+    let section = Section::default()
+        .add_text(Text::new(TEXT).with_scale(SCALE).with_color([1.0; 4]))
+        .with_screen_position((4.0, 8.0));
+    brush.queue(device, queue, [section]).unwrap();
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: WIDTH,
+            height: HEIGHT,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (WIDTH * HEIGHT * 4) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let view = target.create_view(&Default::default());
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        brush.draw(&mut rpass);
+    }
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(WIDTH * 4),
+                rows_per_image: None,
+            },
+        },
+        target.size(),
+    );
+    queue.submit([encoder.finish()]);
+
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+    buffer.slice(..).get_mapped_range().unwrap().to_vec()
+}
+
+#[test]
+fn restores_glyph_bitmaps() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let font = include_bytes!("../examples/fonts/DejaVuSans.ttf");
+    for mipmaps in [false, true] {
+        let mut original = brush(&device, font, mipmaps);
+        original
+            .prewarm(
+                &device,
+                &queue,
+                &[FontId(0)],
+                &[PxScale::from(SCALE)],
+                TEXT.chars(),
+            )
+            .unwrap();
+        let mut file = Vec::new();
+        original.save_atlas(&mut file).unwrap();
+
+        let mut restored = brush(&device, font, mipmaps);
+        restored.load_atlas(&device, &queue, &file[..]).unwrap();
+        let image = render(&device, &queue, &mut restored);
+        // Every glyph of the text is cached by restoring the file.
+        assert_eq!(restored.stats().glyphs_uploaded, 0);
+        assert!(image.iter().any(|&value| value > 0));
+
+        let expected = render(&device, &queue, &mut original);
+        assert_eq!(image, expected, "mipmaps: {mipmaps}");
+    }
+}
+
+#[test]
+fn rejects_invalid_files() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let mut original = brush(
+        &device,
+        include_bytes!("../examples/fonts/DejaVuSans.ttf"),
+        false,
+    );
+    original
+        .prewarm(
+            &device,
+            &queue,
+            &[FontId(0)],
+            &[PxScale::from(SCALE)],
+            "abc".chars(),
+        )
+        .unwrap();
+    let mut file = Vec::new();
+    original.save_atlas(&mut file).unwrap();
+
+    let mut other = brush(
+        &device,
+        include_bytes!("../examples/fonts/ClimateCrisis.ttf"),
+        false,
+    );
+    assert!(matches!(
+        other.load_atlas(&device, &queue, &file[..]),
+        Err(AtlasFileError::FontMismatch)
+    ));
+    assert!(matches!(
+        original.load_atlas(&device, &queue, &file[..file.len() - 1]),
+        Err(AtlasFileError::Io(_))
+    ));
+
+    // Swaps the bitmap dimensions of the first glyph, after the header with a
+    // single font hash, the glyph count and the glyph table entry.
+    let width = 8 + 4 + 4 + 8 + 4 + 4 + 2 + 16;
+    let mut swapped = file.clone();
+    swapped[width..width + 4].copy_from_slice(&file[width + 4..width + 8]);
+    swapped[width + 4..width + 8].copy_from_slice(&file[width..width + 4]);
+    assert_ne!(swapped, file);
+    assert!(matches!(
+        original.load_atlas(&device, &queue, &swapped[..]),
+        Err(AtlasFileError::InvalidFormat)
+    ));
+}
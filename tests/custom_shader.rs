@@ -0,0 +1,82 @@
+//! Checks custom shaders against the device and the brush while building.
+//! Skipped when no adapter is available.
+
+use wgpu_text::{BrushBuilder, BrushError, DEFAULT_SHADER};
+
+fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance =
+        wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&Default::default())).ok()?;
+    pollster::block_on(adapter.request_device(&Default::default())).ok()
+}
+
+fn build(device: &wgpu::Device, shader: String) -> Result<(), BrushError> {
+    BrushBuilder::using_font_bytes(include_bytes!("../examples/fonts/DejaVuSans.ttf"))
+        .unwrap()
+        .with_shader(shader)?
+        .try_build(device, 64, 64, wgpu::TextureFormat::Rgba8Unorm)
+        .map(drop)
+}
+
+#[test]
+fn accepts_the_default_shader() {
+    let Some((device, _)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    build(&device, DEFAULT_SHADER.to_owned()).unwrap();
+}
+
+#[test]
+fn rejects_shaders_not_fitting_the_brush() {
+    let Some((device, _)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let shaders = [
+        // Group without a registered bind group layout.
+        DEFAULT_SHADER.replace("return out;", "out.color *= extra.x;\n    return out;")
+            + "@group(1) @binding(0) var<uniform> extra: vec4<f32>;",
+        // Immediate data without an immediate size.
+        DEFAULT_SHADER.replace("return out;", "out.color *= extra.x;\n    return out;")
+            + "var<immediate> extra: vec4<f32>;",
+        // Built-in binding of the wrong type.
+        DEFAULT_SHADER.replace("var<uniform> style: Style", "var<storage> style: Style"),
+        // Fragment input the vertex stage doesn't output.
+        DEFAULT_SHADER.replace(
+            "fn fs_main(in: VertexOutput)",
+            "fn fs_main(in: VertexOutput, @location(7) extra: f32)",
+        ),
+        // Second color target.
+        DEFAULT_SHADER.replace("@location(0) vec4<f32>", "@location(1) vec4<f32>"),
+    ];
+    for shader in shaders {
+        assert!(
+            matches!(
+                build(&device, shader.clone()),
+                Err(BrushError::InvalidShader(_))
+            ),
+            "{shader}"
+        );
+    }
+}
+
+#[test]
+fn rejects_unsupported_immediate_size() {
+    let Some((device, _)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    // Requested without `wgpu::Features::IMMEDIATES`.
+    let result = BrushBuilder::using_font_bytes(include_bytes!(
+        "../examples/fonts/DejaVuSans.ttf"
+    ))
+    .unwrap()
+    .with_immediate_size(16)
+    .try_build(&device, 64, 64, wgpu::TextureFormat::Rgba8Unorm);
+    assert!(matches!(
+        result,
+        Err(BrushError::UnsupportedImmediateSize(0))
+    ));
+}
@@ -0,0 +1,174 @@
+//! Renders text into offscreen targets of different formats and reads the brightness
+//! back. Skipped when no adapter is available.
+
+use wgpu_text::{
+    BrushBuilder, SectionStyle,
+    glyph_brush::{Section, Text},
+};
+
+const SIZE: u32 = 64;
+
+fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance =
+        wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&Default::default())).ok()?;
+    pollster::block_on(adapter.request_device(&Default::default())).ok()
+}
+
+/// Draws a full block glyph in white and returns the red component of its center.
+fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    format: wgpu::TextureFormat,
+    brush_luminance: f32,
+    style: SectionStyle,
+) -> f32 {
+    let mut brush = BrushBuilder::using_font_bytes(include_bytes!(
+        "../examples/fonts/DejaVuSans.ttf"
+    ))
+    .unwrap()
+    .with_luminance(brush_luminance)
+    .build(device, SIZE, SIZE, format);
+    let section = Section::default()
+        .add_text(
+            Text::new("\u{2588}")
+                .with_scale(SIZE as f32)
+                .with_color([1.0; 4]),
+        )
+        .with_screen_position((8.0, 0.0));
+    let bounds = brush.glyph_bounds(&section).unwrap();
+    brush.queue(device, queue, [(section, style)]).unwrap();
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: SIZE,
+            height: SIZE,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let texel = format.block_copy_size(None).unwrap();
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (SIZE * SIZE * texel) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let view = target.create_view(&Default::default());
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        brush.draw(&mut rpass);
+    }
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(SIZE * texel),
+                rows_per_image: None,
+            },
+        },
+        target.size(),
+    );
+    queue.submit([encoder.finish()]);
+
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+    let data = buffer.slice(..).get_mapped_range().unwrap();
+    let (x, y) = (
+        ((bounds.min.x + bounds.max.x) / 2.0) as u32,
+        ((bounds.min.y + bounds.max.y) / 2.0) as u32,
+    );
+    let offset = ((y * SIZE + x) * texel) as usize;
+    let bytes = &data[offset..offset + texel as usize];
+    match format {
+        wgpu::TextureFormat::Rgba16Float => {
+            f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]]))
+        }
+        wgpu::TextureFormat::Rgb10a2Unorm => {
+            let packed = u32::from_le_bytes(bytes.try_into().unwrap());
+            (packed & 0x3ff) as f32 / 1023.0
+        }
+        wgpu::TextureFormat::Rgba8Unorm => bytes[0] as f32 / 255.0,
+        _ => unreachable!(),
+    }
+}
+
+/// Converts a normal or zero half precision float.
+fn f16_to_f32(half: u16) -> f32 {
+    let exponent = (half >> 10) & 0x1f;
+    if exponent == 0 {
+        return 0.0;
+    }
+    let mantissa = (half & 0x3ff) as f32 / 1024.0;
+    let sign = if half >> 15 == 1 { -1.0 } else { 1.0 };
+    sign * (1.0 + mantissa) * 2f32.powi(exponent as i32 - 15)
+}
+
+fn assert_close(actual: f32, expected: f32, format: wgpu::TextureFormat) {
+    assert!(
+        (actual - expected).abs() < 0.01,
+        "{format:?}: expected {expected}, got {actual}"
+    );
+}
+
+#[test]
+fn brush_luminance() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let style = SectionStyle::default();
+    for (format, expected) in [
+        (wgpu::TextureFormat::Rgba16Float, [0.5, 1.0, 3.0]),
+        // Unorm formats clamp the output.
+        (wgpu::TextureFormat::Rgb10a2Unorm, [0.5, 1.0, 1.0]),
+        (wgpu::TextureFormat::Rgba8Unorm, [0.5, 1.0, 1.0]),
+    ] {
+        for (luminance, expected) in [0.5, 1.0, 3.0].into_iter().zip(expected) {
+            let actual = render(&device, &queue, format, luminance, style);
+            assert_close(actual, expected, format);
+        }
+    }
+}
+
+#[test]
+fn section_luminance() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let format = wgpu::TextureFormat::Rgba16Float;
+    let bright = SectionStyle::default().with_luminance(4.0);
+    assert_close(render(&device, &queue, format, 1.0, bright), 4.0, format);
+    // Multiplied with the brush luminance.
+    assert_close(render(&device, &queue, format, 0.5, bright), 2.0, format);
+
+    let format = wgpu::TextureFormat::Rgb10a2Unorm;
+    let dim = SectionStyle::default().with_luminance(0.25);
+    assert_close(render(&device, &queue, format, 2.0, dim), 0.5, format);
+}